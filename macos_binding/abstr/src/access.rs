@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use bitflags::bitflags;
+use crate::event::FsEvent;
+
+// inotify and fanotify share these bits.
+const ACCESS: u64 = 0x01;
+const CLOSE_WRITE: u64 = 0x08;
+const CLOSE_NOWRITE: u64 = 0x10;
+const OPEN: u64 = 0x20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AccessKind {
+    Opened,
+    Accessed,
+    ClosedWrite,
+    ClosedNoWrite,
+}
+
+impl AccessKind {
+    pub fn mask(self) -> AccessMask {
+        match self {
+            AccessKind::Opened => AccessMask::OPENED,
+            AccessKind::Accessed => AccessMask::ACCESSED,
+            AccessKind::ClosedWrite => AccessMask::CLOSED_WRITE,
+            AccessKind::ClosedNoWrite => AccessMask::CLOSED_NO_WRITE,
+        }
+    }
+
+    // The kinds in an inotify event mask or a fanotify event mask, in the order they
+    // happen.
+    pub fn from_raw(mask: u64) -> Vec<AccessKind> {
+        [(OPEN, AccessKind::Opened), (ACCESS, AccessKind::Accessed), (CLOSE_WRITE, AccessKind::ClosedWrite), (CLOSE_NOWRITE, AccessKind::ClosedNoWrite)]
+            .into_iter()
+            .filter(|(bit, _)| mask & bit != 0)
+            .map(|(_, kind)| kind)
+            .collect()
+    }
+}
+
+bitflags! {
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+    pub struct AccessMask: u8 {
+        const OPENED          = 0x01;
+        const ACCESSED        = 0x02;
+        const CLOSED_WRITE    = 0x04;
+        const CLOSED_NO_WRITE = 0x08;
+    }
+}
+
+impl AccessMask {
+    // The bits to add to the inotify or fanotify mask of a watch.
+    pub fn to_raw(self) -> u64 {
+        let mut raw = 0;
+        if self.contains(AccessMask::OPENED) {
+            raw |= OPEN;
+        }
+        if self.contains(AccessMask::ACCESSED) {
+            raw |= ACCESS;
+        }
+        if self.contains(AccessMask::CLOSED_WRITE) {
+            raw |= CLOSE_WRITE;
+        }
+        if self.contains(AccessMask::CLOSED_NO_WRITE) {
+            raw |= CLOSE_NOWRITE;
+        }
+        raw
+    }
+}
+
+// Which access kinds each watch asked for; a path gets the mask of the closest watch at
+// or above it and nothing when no watch asked. Access events are far more frequent than
+// changes, so backends only subscribe to the kinds in `raw_mask` and `filter` drops what
+// a watch below a broader one does not want.
+#[derive(Clone, Debug, Default)]
+pub struct AccessMasks {
+    masks: BTreeMap<PathBuf, AccessMask>,
+}
+
+impl AccessMasks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set<P>(&mut self, path: P, mask: AccessMask) where P: Into<PathBuf> {
+        let path = path.into();
+        if mask.is_empty() {
+            self.masks.remove(&path);
+        } else {
+            self.masks.insert(path, mask);
+        }
+    }
+
+    pub fn remove(&mut self, path: &Path) {
+        self.masks.remove(path);
+    }
+
+    pub fn mask_for(&self, path: &Path) -> AccessMask {
+        path.ancestors()
+            .find_map(|ancestor| self.masks.get(ancestor))
+            .copied()
+            .unwrap_or(AccessMask::empty())
+    }
+
+    pub fn raw_mask(&self, watch: &Path) -> u64 {
+        self.mask_for(watch).to_raw()
+    }
+
+    pub fn filter(&self, events: Vec<FsEvent>) -> Vec<FsEvent> {
+        events
+            .into_iter()
+            .filter(|event| match event.access {
+                Some(kind) => self.mask_for(&event.path).contains(kind.mask()),
+                None => true,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::FSEventStreamEventFlags;
+
+    fn access(path: &str, kind: AccessKind) -> FsEvent {
+        FsEvent::new(path, FSEventStreamEventFlags::ITEM_IS_FILE, 1).with_access(kind)
+    }
+
+    #[test]
+    fn test_raw_masks() {
+        assert_eq!(AccessKind::from_raw(0x20 | 0x10 | 0x02), vec![AccessKind::Opened, AccessKind::ClosedNoWrite]);
+        assert_eq!((AccessMask::OPENED | AccessMask::CLOSED_WRITE).to_raw(), 0x28);
+        assert_eq!(AccessMask::all().to_raw(), 0x39);
+    }
+
+    #[test]
+    fn test_filter_by_closest_watch() {
+        let mut masks = AccessMasks::new();
+        masks.set("/w", AccessMask::all());
+        masks.set("/w/cold", AccessMask::OPENED);
+
+        let modified = FsEvent::new("/x/file", FSEventStreamEventFlags::ITEM_MODIFIED, 1);
+        let events = masks.filter(vec![
+            access("/w/file", AccessKind::Accessed),
+            access("/w/cold/file", AccessKind::Accessed),
+            access("/w/cold/file", AccessKind::Opened),
+            access("/x/file", AccessKind::Opened),
+            modified.clone(),
+        ]);
+
+        assert_eq!(events, vec![
+            access("/w/file", AccessKind::Accessed),
+            access("/w/cold/file", AccessKind::Opened),
+            modified,
+        ]);
+        assert_eq!(masks.raw_mask(Path::new("/w/cold/sub")), 0x20);
+        assert_eq!(masks.raw_mask(Path::new("/x")), 0);
+    }
+}
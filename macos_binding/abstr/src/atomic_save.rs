@@ -0,0 +1,363 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use crate::event::{path_exists, rename_direction, FSEventStreamEventFlags, FsEvent};
+
+// How a tool names the temporary files it writes next to the file it saves.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SavePattern {
+    // `prefix` + name of the saved file + `suffix`, for example `.foo.swp`.
+    Affix { prefix: String, suffix: String },
+    // Any name starting with the prefix; the saved file is only known from the rename.
+    Prefix(String),
+    Exact(String),
+}
+
+impl SavePattern {
+    pub fn affix(prefix: &str, suffix: &str) -> Self {
+        SavePattern::Affix { prefix: prefix.to_string(), suffix: suffix.to_string() }
+    }
+
+    pub fn prefix(prefix: &str) -> Self {
+        SavePattern::Prefix(prefix.to_string())
+    }
+
+    pub fn exact(name: &str) -> Self {
+        SavePattern::Exact(name.to_string())
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        match self {
+            SavePattern::Affix { prefix, suffix } => {
+                name.len() > prefix.len() + suffix.len() && name.starts_with(prefix.as_str()) && name.ends_with(suffix.as_str())
+            }
+            SavePattern::Prefix(prefix) => name.len() > prefix.len() && name.starts_with(prefix.as_str()),
+            SavePattern::Exact(exact) => name == exact,
+        }
+    }
+
+    // The name of the file a matching temp file belongs to, when the pattern tells.
+    fn target<'a>(&self, name: &'a str) -> Option<&'a str> {
+        match self {
+            SavePattern::Affix { prefix, suffix } => Some(&name[prefix.len()..name.len() - suffix.len()]),
+            SavePattern::Prefix(_) | SavePattern::Exact(_) => None,
+        }
+    }
+}
+
+pub fn default_patterns() -> Vec<SavePattern> {
+    vec![
+        // vim swap files, backups and the file it writes to probe a directory
+        SavePattern::affix(".", ".swp"),
+        SavePattern::affix(".", ".swx"),
+        SavePattern::affix(".", ".swo"),
+        SavePattern::affix("", "~"),
+        SavePattern::exact("4913"),
+        // emacs lock and auto-save files
+        SavePattern::affix(".#", ""),
+        SavePattern::affix("#", "#"),
+        // JetBrains "safe write"
+        SavePattern::affix("", "___jb_tmp___"),
+        SavePattern::affix("", "___jb_old___"),
+        // Kate
+        SavePattern::affix("", ".kate-swp"),
+        // LibreOffice lock files
+        SavePattern::affix(".~lock.", "#"),
+        // GIO (gedit, Nautilus) and generic tools
+        SavePattern::prefix(".goutputstream-"),
+        SavePattern::affix("", ".tmp"),
+    ]
+}
+
+// Turns the bursts editors produce when saving (write a temp file and rename it over the
+// target, move the target aside and write a new one, delete and recreate it) into a
+// single ITEM_MODIFIED for the saved file. Events on temp files are only dropped when they
+// belong to such a save: a temp file of the saved file, or one whose name does not say
+// which file it belongs to next to it. Other files that look like temp files (`notes.tmp`
+// on its own) pass through.
+//
+// Batches are looked at on their own: a save that is split over two callbacks keeps the
+// raw events of both its temp files and the real file.
+#[derive(Clone, Debug)]
+pub struct AtomicSaveNormalizer {
+    patterns: Vec<SavePattern>,
+}
+
+impl Default for AtomicSaveNormalizer {
+    fn default() -> Self {
+        AtomicSaveNormalizer { patterns: default_patterns() }
+    }
+}
+
+impl AtomicSaveNormalizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn without_defaults() -> Self {
+        AtomicSaveNormalizer { patterns: Vec::new() }
+    }
+
+    pub fn with_pattern(mut self, pattern: SavePattern) -> Self {
+        self.add_pattern(pattern);
+        self
+    }
+
+    pub fn add_pattern(&mut self, pattern: SavePattern) {
+        self.patterns.push(pattern);
+    }
+
+    pub fn patterns(&self) -> &[SavePattern] {
+        &self.patterns
+    }
+
+    pub fn is_temp(&self, path: &Path) -> bool {
+        match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => self.patterns.iter().any(|pattern| pattern.matches(name)),
+            None => false,
+        }
+    }
+
+    fn is_temp_of(&self, temp: &Path, saved: &Path) -> bool {
+        let (Some(name), Some(saved_name)) = (temp.file_name().and_then(|name| name.to_str()), saved.file_name()) else {
+            return false;
+        };
+
+        temp.parent() == saved.parent() && self.patterns
+            .iter()
+            .filter(|pattern| pattern.matches(name))
+            .any(|pattern| pattern.target(name).is_none_or(|target| saved_name == target))
+    }
+
+    pub fn process(&self, events: Vec<FsEvent>) -> Vec<FsEvent> {
+        let mut batch = Batch::default();
+        let mut index = 0;
+
+        while index < events.len() {
+            let event = &events[index];
+
+            if let Some((from, to)) = events.get(index + 1).and_then(|next| rename_direction(event, next)) {
+                let pair = vec![event.clone(), events[index + 1].clone()];
+                let last = events[index + 1].id;
+                index += 2;
+
+                match (self.is_temp(from), self.is_temp(to)) {
+                    (true, false) => batch.saved(to, last),
+                    // The saved file moved aside as a backup; the new version follows.
+                    (false, true) => batch.displaced(from, pair),
+                    (true, true) => batch.temp(from, pair),
+                    (false, false) => batch.pass(pair),
+                }
+                continue;
+            }
+
+            index += 1;
+            if self.is_temp(&event.path) {
+                batch.temp(&event.path, vec![event.clone()]);
+                continue;
+            }
+            batch.record(event);
+        }
+
+        let part_of_save: Vec<usize> = batch.temps
+            .iter()
+            .filter(|(_, temp)| batch.saved.keys().any(|saved| self.is_temp_of(temp, saved)))
+            .map(|(slot, _)| *slot)
+            .collect();
+        for slot in part_of_save {
+            batch.slots[slot].clear();
+        }
+
+        batch.finish()
+    }
+}
+
+#[derive(Default)]
+struct Batch {
+    slots: Vec<Vec<FsEvent>>,
+    // Real files that were removed or moved aside and may be recreated later in the batch.
+    displaced: HashMap<PathBuf, usize>,
+    saved: HashMap<PathBuf, usize>,
+    // Temp file events, dropped in the end if they belong to a save.
+    temps: Vec<(usize, PathBuf)>,
+}
+
+impl Batch {
+    fn pass(&mut self, events: Vec<FsEvent>) {
+        self.slots.push(events);
+    }
+
+    fn temp(&mut self, path: &Path, events: Vec<FsEvent>) {
+        self.temps.push((self.slots.len(), path.to_path_buf()));
+        self.slots.push(events);
+    }
+
+    fn saved(&mut self, path: &Path, id: u64) {
+        if let Some(slot) = self.displaced.remove(path) {
+            self.slots[slot].clear();
+        }
+
+        let event = FsEvent::new(path, FSEventStreamEventFlags::ITEM_MODIFIED | FSEventStreamEventFlags::ITEM_IS_FILE, id);
+        match self.saved.get(path) {
+            Some(&slot) => self.slots[slot] = vec![event],
+            None => {
+                self.saved.insert(path.to_path_buf(), self.slots.len());
+                self.slots.push(vec![event]);
+            }
+        }
+    }
+
+    fn displaced(&mut self, path: &Path, events: Vec<FsEvent>) {
+        self.displaced.insert(path.to_path_buf(), self.slots.len());
+        self.slots.push(events);
+    }
+
+    fn record(&mut self, event: &FsEvent) {
+        let flags = event.flags;
+        if flags.contains(FSEventStreamEventFlags::ITEM_IS_DIR) {
+            self.pass(vec![event.clone()]);
+            return;
+        }
+
+        let created = flags.intersects(FSEventStreamEventFlags::ITEM_CREATED | FSEventStreamEventFlags::ITEM_RENAMED);
+        let removed = flags.contains(FSEventStreamEventFlags::ITEM_REMOVED);
+        let exists = path_exists(&event.path);
+
+        if exists && (self.displaced.contains_key(&event.path) || self.saved.contains_key(&event.path)) && created {
+            self.saved(&event.path, event.id);
+        } else if exists && created && removed {
+            // Deleted and recreated between two callbacks, coalesced into one event.
+            self.saved(&event.path, event.id);
+        } else if removed && !created {
+            self.displaced(&event.path, vec![event.clone()]);
+        } else if exists && self.saved.contains_key(&event.path) && !removed {
+            // Already reported as saved; further writes to it are part of the same save.
+            self.saved(&event.path, event.id);
+        } else {
+            self.pass(vec![event.clone()]);
+        }
+    }
+
+    fn finish(self) -> Vec<FsEvent> {
+        self.slots.into_iter().flatten().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::utils::TestDir;
+
+    const FILE: FSEventStreamEventFlags = FSEventStreamEventFlags::ITEM_IS_FILE;
+
+    fn modified(path: PathBuf, id: u64) -> FsEvent {
+        FsEvent::new(path, FSEventStreamEventFlags::ITEM_MODIFIED | FILE, id)
+    }
+
+    #[test]
+    fn test_default_patterns() {
+        let normalizer = AtomicSaveNormalizer::new();
+        for temp in [".foo.swp", "foo~", ".#foo", "#foo#", "foo___jb_tmp___", ".~lock.foo#", ".goutputstream-X1Y2", "4913"] {
+            assert!(normalizer.is_temp(Path::new("/w").join(temp).as_path()), "{}", temp);
+        }
+        for real in ["foo", "foo.rs", ".swp", "~", "#"] {
+            assert!(!normalizer.is_temp(Path::new("/w").join(real).as_path()), "{}", real);
+        }
+    }
+
+    #[test]
+    fn test_temp_names_outside_a_save_pass_through() {
+        let dir = TestDir::new("atomic_save_lone_temp");
+        let events = vec![
+            FsEvent::new(dir.path().join("notes.tmp"), FSEventStreamEventFlags::ITEM_CREATED | FSEventStreamEventFlags::ITEM_MODIFIED | FILE, 1),
+            FsEvent::new(dir.path().join("draft~"), FSEventStreamEventFlags::ITEM_MODIFIED | FILE, 2),
+        ];
+
+        assert_eq!(AtomicSaveNormalizer::new().process(events.clone()), events);
+    }
+
+    #[test]
+    fn test_rename_over_target() {
+        let dir = TestDir::new("atomic_save_rename");
+        let target = dir.path().join("foo");
+        let temp = dir.path().join("foo___jb_tmp___");
+        fs::write(&target, "saved").unwrap();
+
+        let events = AtomicSaveNormalizer::new().process(vec![
+            FsEvent::new(&temp, FSEventStreamEventFlags::ITEM_CREATED | FSEventStreamEventFlags::ITEM_MODIFIED | FILE, 1),
+            FsEvent::new(&temp, FSEventStreamEventFlags::ITEM_RENAMED | FILE, 2),
+            FsEvent::new(&target, FSEventStreamEventFlags::ITEM_RENAMED | FILE, 3),
+        ]);
+
+        assert_eq!(events, vec![modified(target, 3)]);
+    }
+
+    #[test]
+    fn test_backup_and_rewrite() {
+        let dir = TestDir::new("atomic_save_backup");
+        let target = dir.path().join("foo");
+        let backup = dir.path().join("foo~");
+        fs::write(&target, "saved").unwrap();
+
+        // vim: move the original aside, write the new file, drop the backup
+        let events = AtomicSaveNormalizer::new().process(vec![
+            FsEvent::new(dir.path().join(".foo.swp"), FSEventStreamEventFlags::ITEM_MODIFIED | FILE, 1),
+            FsEvent::new(&target, FSEventStreamEventFlags::ITEM_RENAMED | FILE, 2),
+            FsEvent::new(&backup, FSEventStreamEventFlags::ITEM_RENAMED | FILE, 3),
+            FsEvent::new(&target, FSEventStreamEventFlags::ITEM_CREATED | FSEventStreamEventFlags::ITEM_MODIFIED | FILE, 4),
+            FsEvent::new(&backup, FSEventStreamEventFlags::ITEM_REMOVED | FILE, 5),
+        ]);
+
+        assert_eq!(events, vec![modified(target, 4)]);
+    }
+
+    #[test]
+    fn test_delete_and_recreate() {
+        let dir = TestDir::new("atomic_save_recreate");
+        let target = dir.path().join("foo");
+        fs::write(&target, "saved").unwrap();
+
+        let normalizer = AtomicSaveNormalizer::new();
+        assert_eq!(normalizer.process(vec![
+            FsEvent::new(&target, FSEventStreamEventFlags::ITEM_REMOVED | FILE, 1),
+            FsEvent::new(&target, FSEventStreamEventFlags::ITEM_CREATED | FILE, 2),
+            FsEvent::new(&target, FSEventStreamEventFlags::ITEM_MODIFIED | FILE, 3),
+        ]), vec![modified(target.clone(), 3)]);
+
+        assert_eq!(normalizer.process(vec![
+            FsEvent::new(&target, FSEventStreamEventFlags::ITEM_CREATED | FSEventStreamEventFlags::ITEM_REMOVED | FILE, 4),
+        ]), vec![modified(target, 4)]);
+    }
+
+    #[test]
+    fn test_unrelated_events_pass_through() {
+        let dir = TestDir::new("atomic_save_passthrough");
+        let removed = FsEvent::new(dir.path().join("gone"), FSEventStreamEventFlags::ITEM_REMOVED | FILE, 1);
+        let renamed = vec![
+            FsEvent::new(dir.path().join("a"), FSEventStreamEventFlags::ITEM_RENAMED | FILE, 2),
+            FsEvent::new(dir.path().join("b"), FSEventStreamEventFlags::ITEM_RENAMED | FILE, 3),
+        ];
+
+        let mut events = vec![removed];
+        events.extend(renamed);
+        assert_eq!(AtomicSaveNormalizer::new().process(events.clone()), events);
+    }
+
+    #[test]
+    fn test_custom_pattern() {
+        let dir = TestDir::new("atomic_save_custom");
+        let target = dir.path().join("foo");
+        let temp = dir.path().join("foo.partial");
+        fs::write(&target, "saved").unwrap();
+
+        let events = vec![
+            FsEvent::new(&temp, FSEventStreamEventFlags::ITEM_RENAMED | FILE, 1),
+            FsEvent::new(&target, FSEventStreamEventFlags::ITEM_RENAMED | FILE, 2),
+        ];
+
+        assert_eq!(AtomicSaveNormalizer::without_defaults().process(events.clone()), events);
+
+        let normalizer = AtomicSaveNormalizer::without_defaults().with_pattern(SavePattern::affix("", ".partial"));
+        assert_eq!(normalizer.process(events), vec![modified(target, 2)]);
+    }
+}
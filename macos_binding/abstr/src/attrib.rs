@@ -0,0 +1,281 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use crate::event::FSEventStreamEventFlags;
+
+const FINDER_INFO: &[u8] = b"com.apple.FinderInfo";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct AttributeState {
+    uid: u32,
+    gid: u32,
+    mode: u32,
+    xattrs: u64,
+    finder_info: Option<u64>,
+}
+
+impl AttributeState {
+    fn capture(path: &Path) -> io::Result<Self> {
+        let metadata = fs::symlink_metadata(path)?;
+        let mut xattrs = DefaultHasher::new();
+        let mut finder_info = None;
+
+        for (name, value) in read_xattrs(path)? {
+            if name == FINDER_INFO {
+                finder_info = Some(digest(&value));
+            } else {
+                (name, value).hash(&mut xattrs);
+            }
+        }
+
+        Ok(AttributeState {
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            mode: metadata.mode(),
+            xattrs: xattrs.finish(),
+            finder_info,
+        })
+    }
+
+    fn changes(&self, current: &AttributeState) -> FSEventStreamEventFlags {
+        let mut flags = FSEventStreamEventFlags::NONE;
+
+        if self.uid != current.uid || self.gid != current.gid {
+            flags |= FSEventStreamEventFlags::ITEM_CHANGE_OWNER;
+        }
+        if self.mode != current.mode {
+            flags |= FSEventStreamEventFlags::ITEM_INODE_META_MOD;
+        }
+        if self.xattrs != current.xattrs {
+            flags |= FSEventStreamEventFlags::ITEM_XATTR_MOD;
+        }
+        if self.finder_info != current.finder_info {
+            flags |= FSEventStreamEventFlags::ITEM_FINDER_INFO_MOD;
+        }
+
+        flags
+    }
+}
+
+// inotify reports every metadata change as IN_ATTRIB. Keeping the ownership, mode and a
+// digest of the extended attributes of each watched entry lets a backend tell them
+// apart again and report the same flags FSEvents would.
+#[derive(Debug, Default)]
+pub struct AttributeCache {
+    entries: HashMap<PathBuf, AttributeState>,
+}
+
+impl AttributeCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn track(&mut self, path: &Path) -> io::Result<()> {
+        let state = AttributeState::capture(path)?;
+        self.entries.insert(path.to_path_buf(), state);
+        Ok(())
+    }
+
+    pub fn forget(&mut self, path: &Path) {
+        self.entries.remove(path);
+    }
+
+    pub fn rename(&mut self, from: &Path, to: &Path) {
+        if let Some(state) = self.entries.remove(from) {
+            self.entries.insert(to.to_path_buf(), state);
+        }
+    }
+
+    // Turns an IN_ATTRIB on `path` into FSEvents flags and remembers the new state. What
+    // is left when nothing we keep track of changed (timestamps, link count) is reported
+    // as ITEM_INODE_META_MOD, as FSEvents does.
+    pub fn classify(&mut self, path: &Path) -> FSEventStreamEventFlags {
+        let current = match AttributeState::capture(path) {
+            Ok(current) => current,
+            Err(_) => {
+                self.forget(path);
+                return FSEventStreamEventFlags::ITEM_INODE_META_MOD;
+            }
+        };
+
+        let flags = match self.entries.insert(path.to_path_buf(), current.clone()) {
+            Some(previous) => previous.changes(&current),
+            None => FSEventStreamEventFlags::NONE,
+        };
+
+        if flags.is_empty() {
+            FSEventStreamEventFlags::ITEM_INODE_META_MOD
+        } else {
+            flags
+        }
+    }
+}
+
+fn digest(value: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn c_path(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes()).map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))
+}
+
+// Names and values, sorted by name; attributes we may not read are left out.
+fn read_xattrs(path: &Path) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let path = c_path(path)?;
+    let names = read_buffer(|buffer, size| unsafe { sys::list(&path, buffer, size) })?;
+
+    let mut xattrs: Vec<(Vec<u8>, Vec<u8>)> = names
+        .split(|byte| *byte == 0)
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| {
+            let c_name = CString::new(name).ok()?;
+            let value = read_buffer(|buffer, size| unsafe { sys::get(&path, &c_name, buffer, size) }).ok()?;
+            Some((name.to_vec(), value))
+        })
+        .collect();
+
+    xattrs.sort();
+    Ok(xattrs)
+}
+
+// Asks for the size first and retries when the attribute grew in between.
+fn read_buffer<F>(read: F) -> io::Result<Vec<u8>>
+    where F: Fn(*mut libc::c_void, usize) -> isize
+{
+    loop {
+        let size = read(std::ptr::null_mut(), 0);
+        if size < 0 {
+            let error = io::Error::last_os_error();
+            return match error.raw_os_error() {
+                Some(code) if code == sys::NOT_SUPPORTED => Ok(Vec::new()),
+                _ => Err(error),
+            };
+        }
+
+        let mut buffer = vec![0u8; size as usize];
+        let read_size = read(buffer.as_mut_ptr() as *mut libc::c_void, buffer.len());
+        if read_size >= 0 {
+            buffer.truncate(read_size as usize);
+            return Ok(buffer);
+        }
+
+        if io::Error::last_os_error().raw_os_error() != Some(libc::ERANGE) {
+            return Err(io::Error::last_os_error());
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::ffi::CStr;
+
+    pub(super) const NOT_SUPPORTED: i32 = libc::ENOTSUP;
+
+    pub(super) unsafe fn list(path: &CStr, buffer: *mut libc::c_void, size: usize) -> isize {
+        libc::llistxattr(path.as_ptr(), buffer as *mut libc::c_char, size)
+    }
+
+    pub(super) unsafe fn get(path: &CStr, name: &CStr, buffer: *mut libc::c_void, size: usize) -> isize {
+        libc::lgetxattr(path.as_ptr(), name.as_ptr(), buffer, size)
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod sys {
+    use std::ffi::CStr;
+
+    pub(super) const NOT_SUPPORTED: i32 = libc::ENOTSUP;
+
+    pub(super) unsafe fn list(path: &CStr, buffer: *mut libc::c_void, size: usize) -> isize {
+        libc::listxattr(path.as_ptr(), buffer as *mut libc::c_char, size, libc::XATTR_NOFOLLOW)
+    }
+
+    pub(super) unsafe fn get(path: &CStr, name: &CStr, buffer: *mut libc::c_void, size: usize) -> isize {
+        libc::getxattr(path.as_ptr(), name.as_ptr(), buffer, size, 0, libc::XATTR_NOFOLLOW)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{chown, PermissionsExt};
+    use crate::utils::TestDir;
+
+    #[cfg(target_os = "linux")]
+    fn set_xattr(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+        let path = c_path(path).unwrap();
+        let name = CString::new(name).unwrap();
+        let result = unsafe { libc::lsetxattr(path.as_ptr(), name.as_ptr(), value.as_ptr() as *const libc::c_void, value.len(), 0) };
+        if result == 0 { Ok(()) } else { Err(io::Error::last_os_error()) }
+    }
+
+    #[test]
+    fn test_classify_mode_and_owner() {
+        let dir = TestDir::new("attrib_mode");
+        let file = dir.path().join("file");
+        fs::write(&file, "a").unwrap();
+
+        let mut cache = AttributeCache::new();
+        cache.track(&file).unwrap();
+
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(cache.classify(&file), FSEventStreamEventFlags::ITEM_INODE_META_MOD);
+
+        let uid = fs::metadata(&file).unwrap().uid();
+        if chown(&file, Some(uid), Some(12345)).is_ok() {
+            assert_eq!(cache.classify(&file), FSEventStreamEventFlags::ITEM_CHANGE_OWNER);
+        }
+
+        // Nothing we keep changed, e.g. only the timestamps were touched.
+        assert_eq!(cache.classify(&file), FSEventStreamEventFlags::ITEM_INODE_META_MOD);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_classify_xattrs() {
+        let dir = TestDir::new("attrib_xattr");
+        let file = dir.path().join("file");
+        fs::write(&file, "a").unwrap();
+
+        let mut cache = AttributeCache::new();
+        cache.track(&file).unwrap();
+
+        // Not every file system the tests run on supports user xattrs.
+        if set_xattr(&file, "user.test", b"one").is_err() {
+            return;
+        }
+        assert_eq!(cache.classify(&file), FSEventStreamEventFlags::ITEM_XATTR_MOD);
+
+        set_xattr(&file, "user.test", b"two").unwrap();
+        assert_eq!(cache.classify(&file), FSEventStreamEventFlags::ITEM_XATTR_MOD);
+    }
+
+    #[test]
+    fn test_rename_and_forget() {
+        let dir = TestDir::new("attrib_rename");
+        let file = dir.path().join("file");
+        fs::write(&file, "a").unwrap();
+
+        let mut cache = AttributeCache::new();
+        cache.track(&file).unwrap();
+        cache.rename(&file, &dir.path().join("renamed"));
+        cache.forget(&dir.path().join("renamed"));
+        assert!(cache.is_empty());
+    }
+}
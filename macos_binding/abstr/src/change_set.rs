@@ -0,0 +1,349 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use crate::event::{path_exists, rename_direction, FSEventStreamEventFlags, FsEvent};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    Created,
+    Modified,
+    Removed,
+    Moved { from: PathBuf, modified: bool },
+}
+
+// Folds a stream of events into the net change per path over a window: a file that was
+// created and removed again disappears, repeated modifications collapse into one and a
+// removed directory takes everything that happened below it along.
+#[derive(Clone, Debug, Default)]
+pub struct ChangeSet {
+    changes: BTreeMap<PathBuf, Change>,
+    pending_rename: Option<FsEvent>,
+}
+
+impl ChangeSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty() && self.pending_rename.is_none()
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&Change> {
+        self.changes.get(path)
+    }
+
+    pub fn changes(&self) -> impl Iterator<Item = (&Path, &Change)> {
+        self.changes.iter().map(|(path, change)| (path.as_path(), change))
+    }
+
+    pub fn drain(&mut self) -> Vec<(PathBuf, Change)> {
+        self.resolve_pending_rename();
+        std::mem::take(&mut self.changes).into_iter().collect()
+    }
+
+    pub fn record(&mut self, event: &FsEvent) {
+        let flags = event.flags;
+
+        if flags.contains(FSEventStreamEventFlags::ITEM_RENAMED) {
+            self.record_rename_event(event);
+            return;
+        }
+
+        self.resolve_pending_rename();
+
+        let created = flags.contains(FSEventStreamEventFlags::ITEM_CREATED);
+        let removed = flags.contains(FSEventStreamEventFlags::ITEM_REMOVED);
+        let modified = flags.intersects(
+            FSEventStreamEventFlags::ITEM_MODIFIED
+                | FSEventStreamEventFlags::ITEM_INODE_META_MOD
+                | FSEventStreamEventFlags::ITEM_CHANGE_OWNER
+                | FSEventStreamEventFlags::ITEM_XATTR_MOD
+                | FSEventStreamEventFlags::ITEM_FINDER_INFO_MOD,
+        );
+
+        // FSEvents coalesces several operations into one event without telling their
+        // order; whether the path still exists decides which one came last.
+        if removed && (created || modified) && path_exists(&event.path) {
+            self.record_removed(&event.path);
+            if created {
+                self.record_created(&event.path);
+            }
+            if modified {
+                self.record_modified(&event.path);
+            }
+            return;
+        }
+
+        if created {
+            self.record_created(&event.path);
+        }
+        if modified {
+            self.record_modified(&event.path);
+        }
+        if removed {
+            self.record_removed(&event.path);
+        }
+    }
+
+    pub fn record_created(&mut self, path: &Path) {
+        let change = match self.changes.remove(path) {
+            None | Some(Change::Created) => Change::Created,
+            Some(Change::Removed) | Some(Change::Modified) => Change::Modified,
+            Some(Change::Moved { from, .. }) => Change::Moved { from, modified: true },
+        };
+        self.changes.insert(path.to_path_buf(), change);
+    }
+
+    pub fn record_modified(&mut self, path: &Path) {
+        let change = match self.changes.remove(path) {
+            Some(Change::Created) => Change::Created,
+            Some(Change::Moved { from, .. }) => Change::Moved { from, modified: true },
+            None | Some(Change::Modified) | Some(Change::Removed) => Change::Modified,
+        };
+        self.changes.insert(path.to_path_buf(), change);
+    }
+
+    pub fn record_removed(&mut self, path: &Path) {
+        let mut existed_before = true;
+
+        for (descendant, change) in self.take_subtree(path) {
+            match change {
+                Change::Created if descendant == path => existed_before = false,
+                // Moved in from outside and gone with the rest: the source was removed.
+                Change::Moved { from, .. } if !from.starts_with(path) => {
+                    if descendant == path {
+                        existed_before = false;
+                    }
+                    self.record_removed(&from);
+                }
+                Change::Moved { .. } if descendant == path => existed_before = false,
+                _ => {}
+            }
+        }
+
+        if existed_before {
+            self.changes.insert(path.to_path_buf(), Change::Removed);
+        }
+    }
+
+    pub fn record_rename(&mut self, from: &Path, to: &Path) {
+        if from == to {
+            return;
+        }
+
+        let moved = self.take_subtree(from);
+
+        // Whatever was at the destination is replaced; if it had been moved there from
+        // elsewhere, that source is now gone for good.
+        let mut replaced = None;
+        for (path, change) in self.take_subtree(to) {
+            if let Change::Moved { from: origin, .. } = &change {
+                self.record_removed(origin);
+            }
+            if path == to {
+                replaced = Some(change);
+            }
+        }
+        let destination_existed = matches!(replaced, Some(Change::Modified) | Some(Change::Removed));
+
+        let change = match moved.iter().find(|(path, _)| path == from).map(|(_, change)| change.clone()) {
+            Some(Change::Created) if destination_existed => Some(Change::Modified),
+            Some(Change::Created) => Some(Change::Created),
+            // Moving an item back to where it started is no change at all.
+            Some(Change::Moved { from: origin, modified }) if origin == to => {
+                if modified { Some(Change::Modified) } else { None }
+            }
+            Some(Change::Moved { from: origin, modified }) => Some(Change::Moved { from: origin, modified }),
+            Some(Change::Modified) => Some(Change::Moved { from: from.to_path_buf(), modified: true }),
+            None | Some(Change::Removed) => Some(Change::Moved { from: from.to_path_buf(), modified: false }),
+        };
+
+        if let Some(change) = change {
+            self.changes.insert(to.to_path_buf(), change);
+        }
+
+        for (path, change) in moved {
+            if path == from {
+                continue;
+            }
+
+            let relative = path.strip_prefix(from).unwrap();
+            self.changes.insert(to.join(relative), change);
+        }
+    }
+
+    fn record_rename_event(&mut self, event: &FsEvent) {
+        let pending = match self.pending_rename.take() {
+            Some(pending) => pending,
+            None => {
+                self.pending_rename = Some(event.clone());
+                return;
+            }
+        };
+
+        match rename_direction(&pending, event) {
+            Some((from, to)) => self.record_rename(from, to),
+            None => {
+                self.record_lone_rename(&pending);
+                self.pending_rename = Some(event.clone());
+            }
+        }
+    }
+
+    fn resolve_pending_rename(&mut self) {
+        if let Some(pending) = self.pending_rename.take() {
+            self.record_lone_rename(&pending);
+        }
+    }
+
+    // One half of a rename whose other end is outside the watched paths.
+    fn record_lone_rename(&mut self, event: &FsEvent) {
+        if path_exists(&event.path) {
+            self.record_created(&event.path);
+        } else {
+            self.record_removed(&event.path);
+        }
+    }
+
+    fn take_subtree(&mut self, path: &Path) -> Vec<(PathBuf, Change)> {
+        let paths: Vec<PathBuf> = self.changes
+            .range::<Path, _>((Bound::Included(path), Bound::Unbounded))
+            .take_while(|(descendant, _)| descendant.starts_with(path))
+            .map(|(descendant, _)| descendant.clone())
+            .collect();
+
+        paths
+            .into_iter()
+            .map(|descendant| {
+                let change = self.changes.remove(&descendant).unwrap();
+                (descendant, change)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TestDir;
+
+    fn path(value: &str) -> PathBuf {
+        PathBuf::from(value)
+    }
+
+    #[test]
+    fn test_create_then_remove_vanishes() {
+        let mut changes = ChangeSet::new();
+        changes.record_created(&path("/w/a"));
+        changes.record_modified(&path("/w/a"));
+        changes.record_removed(&path("/w/a"));
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn test_repeated_modifications_collapse() {
+        let mut changes = ChangeSet::new();
+        for _ in 0..3 {
+            changes.record_modified(&path("/w/a"));
+        }
+        assert_eq!(changes.drain(), vec![(path("/w/a"), Change::Modified)]);
+    }
+
+    #[test]
+    fn test_remove_then_create_is_modification() {
+        let mut changes = ChangeSet::new();
+        changes.record_removed(&path("/w/a"));
+        changes.record_created(&path("/w/a"));
+        assert_eq!(changes.drain(), vec![(path("/w/a"), Change::Modified)]);
+    }
+
+    #[test]
+    fn test_create_then_rename_is_create_at_final_path() {
+        let mut changes = ChangeSet::new();
+        changes.record_created(&path("/w/tmp"));
+        changes.record_rename(&path("/w/tmp"), &path("/w/final"));
+        assert_eq!(changes.drain(), vec![(path("/w/final"), Change::Created)]);
+    }
+
+    #[test]
+    fn test_create_then_rename_over_existing_is_modification() {
+        let mut changes = ChangeSet::new();
+        changes.record_modified(&path("/w/final"));
+        changes.record_created(&path("/w/.final.swp"));
+        changes.record_rename(&path("/w/.final.swp"), &path("/w/final"));
+        assert_eq!(changes.drain(), vec![(path("/w/final"), Change::Modified)]);
+    }
+
+    #[test]
+    fn test_rename_chain_collapses() {
+        let mut changes = ChangeSet::new();
+        changes.record_rename(&path("/w/a"), &path("/w/b"));
+        changes.record_rename(&path("/w/b"), &path("/w/c"));
+        assert_eq!(changes.drain(), vec![(path("/w/c"), Change::Moved { from: path("/w/a"), modified: false })]);
+
+        changes.record_rename(&path("/w/a"), &path("/w/b"));
+        changes.record_rename(&path("/w/b"), &path("/w/a"));
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn test_directory_removal_subsumes_children() {
+        let mut changes = ChangeSet::new();
+        changes.record_created(&path("/w/dir/new"));
+        changes.record_modified(&path("/w/dir/old"));
+        changes.record_removed(&path("/w/dir/gone"));
+        changes.record_rename(&path("/w/outside"), &path("/w/dir/moved"));
+        changes.record_removed(&path("/w/dir"));
+
+        assert_eq!(changes.drain(), vec![
+            (path("/w/dir"), Change::Removed),
+            (path("/w/outside"), Change::Removed),
+        ]);
+    }
+
+    #[test]
+    fn test_directory_rename_moves_children() {
+        let mut changes = ChangeSet::new();
+        changes.record_created(&path("/w/dir/new"));
+        changes.record_rename(&path("/w/dir"), &path("/w/renamed"));
+
+        assert_eq!(changes.drain(), vec![
+            (path("/w/renamed"), Change::Moved { from: path("/w/dir"), modified: false }),
+            (path("/w/renamed/new"), Change::Created),
+        ]);
+    }
+
+    #[test]
+    fn test_record_pairs_rename_events() {
+        let dir = TestDir::new("change_set_rename");
+        std::fs::write(dir.path().join("final"), "a").unwrap();
+
+        let mut changes = ChangeSet::new();
+        changes.record(&FsEvent::new(dir.path().join("tmp"), FSEventStreamEventFlags::ITEM_CREATED | FSEventStreamEventFlags::ITEM_IS_FILE, 10));
+        changes.record(&FsEvent::new(dir.path().join("tmp"), FSEventStreamEventFlags::ITEM_RENAMED | FSEventStreamEventFlags::ITEM_IS_FILE, 11));
+        changes.record(&FsEvent::new(dir.path().join("final"), FSEventStreamEventFlags::ITEM_RENAMED | FSEventStreamEventFlags::ITEM_IS_FILE, 12));
+
+        assert_eq!(changes.drain(), vec![(dir.path().join("final"), Change::Created)]);
+    }
+
+    #[test]
+    fn test_record_pairs_rescanned_rename_events() {
+        let dir = TestDir::new("change_set_rescanned_rename");
+        std::fs::write(dir.path().join("after"), "a").unwrap();
+
+        let mut snapshot = crate::snapshot::Snapshot::capture(dir.path()).unwrap();
+        std::fs::rename(dir.path().join("after"), dir.path().join("before")).unwrap();
+        snapshot.rescan(dir.path(), 5);
+        std::fs::rename(dir.path().join("before"), dir.path().join("after")).unwrap();
+
+        let mut changes = ChangeSet::new();
+        for event in snapshot.rescan(dir.path(), u64::MAX) {
+            changes.record(&event);
+        }
+
+        assert_eq!(changes.drain(), vec![
+            (dir.path().join("after"), Change::Moved { from: dir.path().join("before"), modified: false }),
+        ]);
+    }
+}
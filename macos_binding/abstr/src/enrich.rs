@@ -0,0 +1,160 @@
+use std::fs::{self, FileType as StdFileType, Metadata};
+use std::io;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::event::FsEvent;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Dir,
+    Symlink,
+    Fifo,
+    Socket,
+    BlockDevice,
+    CharDevice,
+    Unknown,
+}
+
+impl From<StdFileType> for FileType {
+    fn from(file_type: StdFileType) -> Self {
+        if file_type.is_file() {
+            FileType::File
+        } else if file_type.is_dir() {
+            FileType::Dir
+        } else if file_type.is_symlink() {
+            FileType::Symlink
+        } else if file_type.is_fifo() {
+            FileType::Fifo
+        } else if file_type.is_socket() {
+            FileType::Socket
+        } else if file_type.is_block_device() {
+            FileType::BlockDevice
+        } else if file_type.is_char_device() {
+            FileType::CharDevice
+        } else {
+            FileType::Unknown
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EventMetadata {
+    pub file_type: FileType,
+    pub size: u64,
+    pub mtime: SystemTime,
+    pub mode: u32,
+    pub device: u64,
+    pub inode: u64,
+    pub nlink: u64,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl From<&Metadata> for EventMetadata {
+    fn from(metadata: &Metadata) -> Self {
+        EventMetadata {
+            file_type: metadata.file_type().into(),
+            size: metadata.len(),
+            mtime: system_time(metadata.mtime(), metadata.mtime_nsec()),
+            mode: metadata.mode(),
+            device: metadata.dev(),
+            inode: metadata.ino(),
+            nlink: metadata.nlink(),
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MetadataSnapshot {
+    Present(EventMetadata),
+    // Nothing was at the path any more when the event was enriched.
+    Gone,
+    Unavailable(io::ErrorKind),
+}
+
+impl MetadataSnapshot {
+    // Symlinks are described themselves, not the file they point to.
+    pub fn capture(event: &FsEvent) -> Self {
+        match fs::symlink_metadata(&event.path) {
+            Ok(metadata) => MetadataSnapshot::Present((&metadata).into()),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => MetadataSnapshot::Gone,
+            Err(error) => MetadataSnapshot::Unavailable(error.kind()),
+        }
+    }
+
+    pub fn is_gone(&self) -> bool {
+        matches!(self, MetadataSnapshot::Gone)
+    }
+
+    pub fn metadata(&self) -> Option<&EventMetadata> {
+        match self {
+            MetadataSnapshot::Present(metadata) => Some(metadata),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnrichedEvent {
+    pub event: FsEvent,
+    pub metadata: MetadataSnapshot,
+    pub captured_at: SystemTime,
+}
+
+// Stats every path of a batch right away, so consumers get the state of the file as it
+// was when the event was delivered instead of racing later changes with their own stat.
+// Meant to run first thing in the stream callback.
+pub fn enrich(events: Vec<FsEvent>) -> Vec<EnrichedEvent> {
+    events
+        .into_iter()
+        .map(|event| {
+            let metadata = MetadataSnapshot::capture(&event);
+            EnrichedEvent { event, metadata, captured_at: SystemTime::now() }
+        })
+        .collect()
+}
+
+fn system_time(seconds: i64, nanos: i64) -> SystemTime {
+    let nanos = Duration::from_nanos(nanos as u64);
+    if seconds >= 0 {
+        UNIX_EPOCH + Duration::from_secs(seconds as u64) + nanos
+    } else {
+        UNIX_EPOCH - Duration::from_secs(seconds.unsigned_abs()) + nanos
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::FSEventStreamEventFlags;
+    use crate::utils::TestDir;
+
+    #[test]
+    fn test_enrich_existing_and_gone() {
+        let dir = TestDir::new("enrich");
+        let file = dir.path().join("file");
+        fs::write(&file, "content").unwrap();
+        std::os::unix::fs::symlink(&file, dir.path().join("link")).unwrap();
+
+        let events = enrich(vec![
+            FsEvent::new(&file, FSEventStreamEventFlags::ITEM_MODIFIED | FSEventStreamEventFlags::ITEM_IS_FILE, 1),
+            FsEvent::new(dir.path().join("link"), FSEventStreamEventFlags::ITEM_CREATED | FSEventStreamEventFlags::ITEM_IS_SYMLINK, 2),
+            FsEvent::new(dir.path().join("gone"), FSEventStreamEventFlags::ITEM_REMOVED | FSEventStreamEventFlags::ITEM_IS_FILE, 3),
+        ]);
+
+        let expected = fs::metadata(&file).unwrap();
+        let metadata = events[0].metadata.metadata().unwrap();
+        assert_eq!(metadata.file_type, FileType::File);
+        assert_eq!(metadata.size, 7);
+        assert_eq!(metadata.inode, expected.ino());
+        assert_eq!(metadata.nlink, 1);
+        assert_eq!(metadata.mtime, expected.modified().unwrap());
+
+        assert_eq!(events[1].metadata.metadata().unwrap().file_type, FileType::Symlink);
+        assert!(events[2].metadata.is_gone());
+        assert_eq!(events[2].event.id, 3);
+    }
+}
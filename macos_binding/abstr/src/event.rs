@@ -0,0 +1,111 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use bitflags::bitflags;
+use crate::access::AccessKind;
+use crate::process::ProcessInfo;
+
+pub type FSEventStreamEventId = u64;
+
+bitflags! {
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+    pub struct FSEventStreamEventFlags: u32 {
+        const NONE                   = 0x00000000;
+        const MUST_SCAN_SUB_DIRS     = 0x00000001;
+        const USER_DROPPED           = 0x00000002;
+        const KERNEL_DROPPED         = 0x00000004;
+        const EVENT_IDS_WRAPPED      = 0x00000008;
+        const HISTORY_DONE           = 0x00000010;
+        const ROOT_CHANGED           = 0x00000020;
+        const MOUNT                  = 0x00000040;
+        const UNMOUNT                = 0x00000080;
+        const ITEM_CREATED           = 0x00000100;
+        const ITEM_REMOVED           = 0x00000200;
+        const ITEM_INODE_META_MOD    = 0x00000400;
+        const ITEM_RENAMED           = 0x00000800;
+        const ITEM_MODIFIED          = 0x00001000;
+        const ITEM_FINDER_INFO_MOD   = 0x00002000;
+        const ITEM_CHANGE_OWNER      = 0x00004000;
+        const ITEM_XATTR_MOD         = 0x00008000;
+        const ITEM_IS_FILE           = 0x00010000;
+        const ITEM_IS_DIR            = 0x00020000;
+        const ITEM_IS_SYMLINK        = 0x00040000;
+        const OWN_EVENT              = 0x00080000;
+        const ITEM_IS_HARDLINK       = 0x00100000;
+        const ITEM_IS_LAST_HARDLINK  = 0x00200000;
+        const ITEM_CLONED            = 0x00400000;
+    }
+}
+
+impl FSEventStreamEventFlags {
+    pub const ITEM_TYPE: Self = Self::ITEM_IS_FILE.union(Self::ITEM_IS_DIR).union(Self::ITEM_IS_SYMLINK);
+
+    pub fn needs_rescan(&self) -> bool {
+        self.intersects(Self::MUST_SCAN_SUB_DIRS | Self::USER_DROPPED | Self::KERNEL_DROPPED)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FsEvent {
+    pub path: PathBuf,
+    pub flags: FSEventStreamEventFlags,
+    pub id: FSEventStreamEventId,
+    // Process that caused the event, for backends that are told (fanotify).
+    pub pid: Option<u32>,
+    // Filled in by `process::attribute`; FSEvents does not say who made a change.
+    pub process: Option<ProcessInfo>,
+    // Set on the opt-in read events of backends that see them; the flags then only carry
+    // the item type.
+    pub access: Option<AccessKind>,
+}
+
+impl FsEvent {
+    pub fn new<P>(path: P, flags: FSEventStreamEventFlags, id: FSEventStreamEventId) -> Self
+        where P: Into<PathBuf>
+    {
+        FsEvent { path: path.into(), flags, id, pid: None, process: None, access: None }
+    }
+
+    pub fn with_pid(mut self, pid: u32) -> Self {
+        self.pid = Some(pid);
+        self
+    }
+
+    pub fn with_access(mut self, access: AccessKind) -> Self {
+        self.access = Some(access);
+        self
+    }
+
+    // Builds events from the parallel vectors an FSEvents callback receives.
+    pub fn from_stream(paths: Vec<String>, flags: Vec<FSEventStreamEventFlags>, ids: Vec<FSEventStreamEventId>) -> Vec<FsEvent> {
+        paths
+            .into_iter()
+            .zip(flags)
+            .zip(ids)
+            .map(|((path, flags), id)| FsEvent::new(path, flags, id))
+            .collect()
+    }
+}
+
+// FSEvents reports both ends of a rename as ITEM_RENAMED events with consecutive ids and
+// does not say which one is which; the end that still exists is the destination. Rescans
+// report the renames they find as two adjacent events sharing the rescan's id.
+pub(crate) fn rename_direction<'a>(first: &'a FsEvent, second: &'a FsEvent) -> Option<(&'a Path, &'a Path)> {
+    let renamed = FSEventStreamEventFlags::ITEM_RENAMED;
+    if !first.flags.contains(renamed) || !second.flags.contains(renamed) || first.path == second.path {
+        return None;
+    }
+
+    if first.id.checked_add(1) != Some(second.id) && first.id != second.id {
+        return None;
+    }
+
+    if path_exists(&first.path) && !path_exists(&second.path) {
+        Some((&second.path, &first.path))
+    } else {
+        Some((&first.path, &second.path))
+    }
+}
+
+pub(crate) fn path_exists(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok()
+}
@@ -0,0 +1,145 @@
+use std::path::{Path, PathBuf};
+use crate::event::FsEvent;
+
+// FSEventStreamSetExclusionPaths rejects lists longer than this.
+pub const MAX_KERNEL_EXCLUSIONS: usize = 8;
+
+// PATH_MAX leaves no room for deeper paths.
+const MAX_DEPTH: u64 = 512;
+
+// Splits excluded paths between the few the kernel can filter and the rest, which are
+// filtered in user space after delivery.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExclusionPlan {
+    pub kernel: Vec<PathBuf>,
+    pub user_space: Vec<PathBuf>,
+    // Set when the kernel refused `kernel` and everything is filtered in user space.
+    pub kernel_rejected: bool,
+}
+
+impl ExclusionPlan {
+    // Without better knowledge a shallower path is assumed to hold more and so to save
+    // more events.
+    pub fn new<P>(paths: &[P]) -> Self where P: AsRef<Path> {
+        let weighted: Vec<(&Path, u64)> = paths
+            .iter()
+            .map(|path| (path.as_ref(), MAX_DEPTH.saturating_sub(path.as_ref().components().count() as u64)))
+            .collect();
+        Self::with_weights(&weighted)
+    }
+
+    // `weight` is what excluding the path in the kernel is worth, for example the number
+    // of events seen below it. Paths below another excluded path are dropped, as are
+    // duplicates; the heaviest ones go to the kernel and ties keep the given order.
+    pub fn with_weights<P>(paths: &[(P, u64)]) -> Self where P: AsRef<Path> {
+        let mut candidates: Vec<(usize, &Path, u64)> = Vec::new();
+        for (index, (path, weight)) in paths.iter().enumerate() {
+            let path = path.as_ref();
+            let covered = paths.iter().enumerate().any(|(other_index, (other, _))| {
+                let other = other.as_ref();
+                (other != path && path.starts_with(other)) || (other == path && other_index < index)
+            });
+
+            if !covered {
+                // What is saved below a covered path is saved by its ancestor as well.
+                let weight = paths
+                    .iter()
+                    .filter(|(other, _)| other.as_ref() != path && other.as_ref().starts_with(path))
+                    .fold(*weight, |total, (_, other_weight)| total.saturating_add(*other_weight));
+                candidates.push((index, path, weight));
+            }
+        }
+
+        candidates.sort_by(|(a_index, _, a_weight), (b_index, _, b_weight)| b_weight.cmp(a_weight).then(a_index.cmp(b_index)));
+
+        let mut plan = ExclusionPlan::default();
+        for (position, (_, path, _)) in candidates.into_iter().enumerate() {
+            if position < MAX_KERNEL_EXCLUSIONS {
+                plan.kernel.push(path.to_path_buf());
+            } else {
+                plan.user_space.push(path.to_path_buf());
+            }
+        }
+        plan
+    }
+
+    // For when the kernel refused the list after all.
+    pub fn user_space_only(mut self) -> Self {
+        self.kernel.append(&mut self.user_space);
+        self.user_space = self.kernel;
+        self.kernel = Vec::new();
+        self.kernel_rejected = true;
+        self
+    }
+
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        self.user_space.iter().any(|excluded| path.starts_with(excluded))
+    }
+
+    pub fn filter(&self, events: Vec<FsEvent>) -> Vec<FsEvent> {
+        events.into_iter().filter(|event| !self.is_excluded(&event.path)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(values: &[&str]) -> Vec<PathBuf> {
+        values.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn test_small_lists_go_to_the_kernel() {
+        let plan = ExclusionPlan::new(&["/w/a", "/w/b", "/w/a", "/w/a/sub"]);
+        assert_eq!(plan.kernel, paths(&["/w/a", "/w/b"]));
+        assert!(plan.user_space.is_empty());
+    }
+
+    #[test]
+    fn test_shallow_paths_preferred() {
+        let mut excluded: Vec<String> = (0..8).map(|index| format!("/w/deep/{}/x", index)).collect();
+        excluded.push("/w/node_modules".to_string());
+
+        let plan = ExclusionPlan::new(&excluded);
+        assert_eq!(plan.kernel.len(), MAX_KERNEL_EXCLUSIONS);
+        assert_eq!(plan.kernel[0], PathBuf::from("/w/node_modules"));
+        assert_eq!(plan.user_space, paths(&["/w/deep/7/x"]));
+        assert!(plan.is_excluded(Path::new("/w/deep/7/x/file")));
+        assert!(!plan.is_excluded(Path::new("/w/deep/0/x/file")));
+    }
+
+    #[test]
+    fn test_weights() {
+        let mut weighted: Vec<(PathBuf, u64)> = (0..9).map(|index| (PathBuf::from(format!("/w/{}", index)), 10)).collect();
+        weighted[4].1 = 1;
+        weighted.push((PathBuf::from("/w/0/sub"), 100));
+
+        let plan = ExclusionPlan::with_weights(&weighted);
+        assert_eq!(plan.kernel[0], PathBuf::from("/w/0"));
+        assert_eq!(plan.user_space, paths(&["/w/4"]));
+
+        let plan = plan.user_space_only();
+        assert!(plan.kernel.is_empty());
+        assert_eq!(plan.user_space.len(), 9);
+        assert!(plan.kernel_rejected);
+    }
+
+    #[test]
+    fn test_nested_paths_add_up() {
+        let mut excluded = vec![
+            "/w/a/b/c/d/e/f".to_string(),
+            "/w/a/b/c/d/e/f/g".to_string(),
+            "/w/b".to_string(),
+            "/w/b/c".to_string(),
+            "/s".to_string(),
+        ];
+        excluded.extend((0..7).map(|index| format!("/w/f/{}", index)));
+
+        // Both nested pairs outweigh any single path, but the shallower pair comes first.
+        let plan = ExclusionPlan::new(&excluded);
+        assert_eq!(plan.kernel[..3], paths(&["/w/b", "/w/a/b/c/d/e/f", "/s"]));
+        assert_eq!(plan.user_space, paths(&["/w/f/5", "/w/f/6"]));
+        assert!(!plan.kernel_rejected);
+    }
+}
@@ -0,0 +1,685 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+use crate::event::{FSEventStreamEventFlags, FsEvent};
+use crate::snapshot::visit_tree;
+
+const DEFAULT_IGNORE_FILES: [&str; 2] = [".gitignore", ".ignore"];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Literal(char),
+    AnyChar,
+    // `*`: anything but a separator
+    Star,
+    // `**/`: nothing or any number of directories
+    AnyDirs,
+    // `**` anywhere else: anything
+    AnyPath,
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Glob {
+    tokens: Vec<Token>,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Self {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut tokens = Vec::new();
+        let mut index = 0;
+
+        while index < chars.len() {
+            match chars[index] {
+                '*' if chars.get(index + 1) == Some(&'*') => {
+                    if chars.get(index + 2) == Some(&'/') {
+                        tokens.push(Token::AnyDirs);
+                        index += 3;
+                    } else {
+                        tokens.push(Token::AnyPath);
+                        index += 2;
+                    }
+                }
+                '*' => {
+                    tokens.push(Token::Star);
+                    index += 1;
+                }
+                '?' => {
+                    tokens.push(Token::AnyChar);
+                    index += 1;
+                }
+                '[' => match Self::parse_class(&chars[index + 1..]) {
+                    Some((token, length)) => {
+                        tokens.push(token);
+                        index += length + 1;
+                    }
+                    None => {
+                        tokens.push(Token::Literal('['));
+                        index += 1;
+                    }
+                },
+                '\\' if index + 1 < chars.len() => {
+                    tokens.push(Token::Literal(chars[index + 1]));
+                    index += 2;
+                }
+                literal => {
+                    tokens.push(Token::Literal(literal));
+                    index += 1;
+                }
+            }
+        }
+
+        Glob { tokens }
+    }
+
+    // Parses what follows a `[` up to and including the closing `]`.
+    fn parse_class(chars: &[char]) -> Option<(Token, usize)> {
+        let mut index = 0;
+        let negated = matches!(chars.first(), Some('!') | Some('^'));
+        if negated {
+            index += 1;
+        }
+
+        let mut ranges = Vec::new();
+        let start = index;
+        while index < chars.len() {
+            let first = chars[index];
+            if first == ']' && index > start {
+                return Some((Token::Class { negated, ranges }, index + 1));
+            }
+
+            if chars.get(index + 1) == Some(&'-') && chars.get(index + 2).is_some_and(|last| *last != ']') {
+                ranges.push((first, chars[index + 2]));
+                index += 3;
+            } else {
+                ranges.push((first, first));
+                index += 1;
+            }
+        }
+
+        None
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        let mut memo = vec![None; (self.tokens.len() + 1) * (text.len() + 1)];
+        self.matches_from(0, &text, 0, &mut memo)
+    }
+
+    // Whether the tokens from `token` on match the text from `position` on. Every pair
+    // is decided once, so patterns with several wildcards no longer take exponential
+    // time on long paths.
+    fn matches_from(&self, token: usize, text: &[char], position: usize, memo: &mut Vec<Option<bool>>) -> bool {
+        let key = token * (text.len() + 1) + position;
+        if let Some(matched) = memo[key] {
+            return matched;
+        }
+
+        let rest = &text[position..];
+        let next = token + 1;
+        let matched = match self.tokens.get(token) {
+            None => rest.is_empty(),
+            Some(Token::Star) => (0..=rest.len())
+                .take_while(|skip| *skip == 0 || rest[skip - 1] != '/')
+                .any(|skip| self.matches_from(next, text, position + skip, memo)),
+            Some(Token::AnyPath) => (0..=rest.len()).any(|skip| self.matches_from(next, text, position + skip, memo)),
+            Some(Token::AnyDirs) => {
+                self.matches_from(next, text, position, memo)
+                    || (1..=rest.len()).any(|skip| rest[skip - 1] == '/' && self.matches_from(next, text, position + skip, memo))
+            }
+            Some(Token::AnyChar) => !rest.is_empty() && rest[0] != '/' && self.matches_from(next, text, position + 1, memo),
+            Some(Token::Literal(literal)) => rest.first() == Some(literal) && self.matches_from(next, text, position + 1, memo),
+            Some(Token::Class { negated, ranges }) => match rest.first() {
+                Some(character) if *character != '/' => {
+                    let in_class = ranges.iter().any(|(low, high)| low <= character && character <= high);
+                    in_class != *negated && self.matches_from(next, text, position + 1, memo)
+                }
+                _ => false,
+            },
+        };
+
+        memo[key] = Some(matched);
+        matched
+    }
+}
+
+// One line of an ignore file, or one include/exclude pattern, with gitignore semantics: a
+// pattern without a slash matches the name at any depth, one with a slash is anchored at
+// the directory it belongs to, a trailing slash only matches directories and a leading
+// `!` re-includes what an earlier pattern excluded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rule {
+    glob: Glob,
+    negated: bool,
+    dir_only: bool,
+}
+
+impl Rule {
+    pub fn parse(line: &str, case_insensitive: bool) -> Option<Self> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let line = match line.strip_suffix(' ') {
+            Some(_) if !line.ends_with("\\ ") => line.trim_end(),
+            _ => line,
+        };
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line.strip_prefix('\\').filter(|rest| rest.starts_with(['#', '!'])).unwrap_or(line)),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        if line.is_empty() {
+            return None;
+        }
+
+        let pattern = if line.contains('/') {
+            line.trim_start_matches('/').to_string()
+        } else {
+            format!("**/{}", line)
+        };
+        let pattern = if case_insensitive { pattern.to_lowercase() } else { pattern };
+
+        Some(Rule { glob: Glob::new(&pattern), negated, dir_only })
+    }
+
+    // `relative` is the path relative to the directory the rule belongs to, with `/`
+    // separators and already lowercased when matching case-insensitively.
+    fn matches(&self, relative: &str, is_dir: bool) -> bool {
+        (!self.dir_only || is_dir) && self.glob.matches(relative)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IgnoreFile {
+    pub path: PathBuf,
+    rules: Vec<Rule>,
+}
+
+impl IgnoreFile {
+    pub fn load(path: &Path, case_insensitive: bool) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        Ok(IgnoreFile {
+            path: path.to_path_buf(),
+            rules: content.lines().filter_map(|line| Rule::parse(line, case_insensitive)).collect(),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaseSensitivity {
+    // Probe each root's file system.
+    Auto,
+    Sensitive,
+    Insensitive,
+}
+
+struct Root {
+    path: PathBuf,
+    case_insensitive: bool,
+}
+
+// Drops events for paths that are excluded by glob or by the ignore files found in the
+// watched trees, the same way on every backend. Ignore files are picked up, reloaded and
+// forgotten as events for them or their directories come through, so edits take effect
+// right away.
+pub struct EventFilter {
+    roots: Vec<Root>,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    ignore_file_names: Vec<String>,
+    case_sensitivity: CaseSensitivity,
+    // Compiled per root, since case sensitivity can differ between roots.
+    compiled: Vec<(Vec<Rule>, Vec<Rule>)>,
+    // Keyed by the directory the file is in.
+    ignore_files: BTreeMap<PathBuf, IgnoreFile>,
+}
+
+impl EventFilter {
+    pub fn new<P>(roots: &[P]) -> Self where P: AsRef<Path> {
+        let mut filter = EventFilter {
+            roots: roots.iter().map(|root| Root { path: root.as_ref().to_path_buf(), case_insensitive: false }).collect(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            ignore_file_names: DEFAULT_IGNORE_FILES.iter().map(|name| name.to_string()).collect(),
+            case_sensitivity: CaseSensitivity::Auto,
+            compiled: Vec::new(),
+            ignore_files: BTreeMap::new(),
+        };
+        filter.compile();
+        filter
+    }
+
+    pub fn include(mut self, pattern: &str) -> Self {
+        self.include.push(pattern.to_string());
+        self.compile();
+        self
+    }
+
+    pub fn exclude(mut self, pattern: &str) -> Self {
+        self.exclude.push(pattern.to_string());
+        self.compile();
+        self
+    }
+
+    pub fn with_ignore_file_names(mut self, names: &[&str]) -> Self {
+        self.ignore_file_names = names.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    pub fn with_case_sensitivity(mut self, case_sensitivity: CaseSensitivity) -> Self {
+        self.case_sensitivity = case_sensitivity;
+        self.compile();
+        self
+    }
+
+    // Finds the ignore files already in the watched trees.
+    pub fn load_ignore_files(&mut self) -> io::Result<()> {
+        let roots: Vec<PathBuf> = self.roots.iter().map(|root| root.path.clone()).collect();
+        for root in roots {
+            self.rescan(&root)?;
+        }
+        Ok(())
+    }
+
+    pub fn ignore_files(&self) -> impl Iterator<Item = &Path> {
+        self.ignore_files.values().map(|file| file.path.as_path())
+    }
+
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let index = match self.roots.iter().position(|root| path.starts_with(&root.path)) {
+            Some(index) => index,
+            None => return false,
+        };
+        let root = &self.roots[index];
+        let (include, exclude) = &self.compiled[index];
+
+        let relative = relative_text(path, &root.path, root.case_insensitive);
+        if relative.is_empty() {
+            return false;
+        }
+
+        // Directories are not held to the include patterns, or nothing below them could
+        // ever be included.
+        if !is_dir && !include.is_empty() && !include.iter().any(|rule| rule.matches(&relative, false)) {
+            return true;
+        }
+
+        // Like git, nothing below an excluded or ignored directory can be re-included.
+        let mut prefix = root.path.clone();
+        let components: Vec<Component> = path.strip_prefix(&root.path).unwrap().components().collect();
+        for (position, component) in components.iter().enumerate() {
+            prefix.push(component);
+            let prefix_is_dir = position + 1 < components.len() || is_dir;
+            let relative = relative_text(&prefix, &root.path, root.case_insensitive);
+            if last_match(exclude, &relative, prefix_is_dir) == Some(true) {
+                return true;
+            }
+            if self.ignored_by_files(&prefix, prefix_is_dir, &root.path, root.case_insensitive) {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    pub fn process(&mut self, events: Vec<FsEvent>) -> Vec<FsEvent> {
+        for event in &events {
+            self.update_ignore_files(event);
+        }
+
+        events
+            .into_iter()
+            .filter(|event| !self.is_ignored(&event.path, event.flags.contains(FSEventStreamEventFlags::ITEM_IS_DIR)))
+            .collect()
+    }
+
+    // Streams without per-file events only name the directory that changed, and a
+    // directory that is removed, renamed or rescanned takes the ignore files below it
+    // along.
+    fn update_ignore_files(&mut self, event: &FsEvent) {
+        if self.is_ignore_file(&event.path) {
+            self.reload(&event.path);
+            return;
+        }
+
+        if event.flags.contains(FSEventStreamEventFlags::MUST_SCAN_SUB_DIRS) {
+            // Dropped events may be reported above the roots.
+            let targets: Vec<PathBuf> = self.roots
+                .iter()
+                .filter_map(|root| {
+                    if root.path.starts_with(&event.path) {
+                        Some(root.path.clone())
+                    } else if event.path.starts_with(&root.path) {
+                        Some(event.path.clone())
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+            for target in targets {
+                // What cannot be read any more is forgotten either way.
+                let _ = self.rescan(&target);
+            }
+            return;
+        }
+
+        if event.flags.intersects(FSEventStreamEventFlags::ITEM_IS_FILE | FSEventStreamEventFlags::ITEM_IS_SYMLINK)
+            || !self.roots.iter().any(|root| event.path.starts_with(&root.path))
+        {
+            return;
+        }
+
+        if !fs::symlink_metadata(&event.path).is_ok_and(|metadata| metadata.is_dir()) {
+            self.forget_below(&event.path);
+        } else if event.flags.contains(FSEventStreamEventFlags::ITEM_RENAMED) {
+            let _ = self.rescan(&event.path);
+        } else {
+            self.reload_dir(&event.path);
+        }
+    }
+
+    fn rescan(&mut self, dir: &Path) -> io::Result<()> {
+        self.forget_below(dir);
+
+        let mut found = Vec::new();
+        visit_tree(dir, &mut |path, metadata| {
+            if metadata.is_file() && self.is_ignore_file(path) {
+                found.push(path.to_path_buf());
+            }
+        })?;
+
+        for path in found {
+            self.reload(&path);
+        }
+        Ok(())
+    }
+
+    fn forget_below(&mut self, path: &Path) {
+        self.ignore_files.retain(|dir, _| !dir.starts_with(path));
+    }
+
+    fn ignored_by_files(&self, path: &Path, is_dir: bool, root: &Path, case_insensitive: bool) -> bool {
+        let mut ignored = false;
+
+        // Deeper files override shallower ones, the last matching line within a file wins.
+        for dir in path.ancestors().skip(1).take_while(|dir| dir.starts_with(root)).collect::<Vec<&Path>>().into_iter().rev() {
+            if let Some(file) = self.ignore_files.get(dir) {
+                let relative = relative_text(path, dir, case_insensitive);
+                if let Some(excluded) = last_match(&file.rules, &relative, is_dir) {
+                    ignored = excluded;
+                }
+            }
+        }
+
+        ignored
+    }
+
+    fn is_ignore_file(&self, path: &Path) -> bool {
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name,
+            None => return false,
+        };
+
+        self.roots.iter().any(|root| path.starts_with(&root.path))
+            && self.ignore_file_names.iter().any(|ignore_name| ignore_name == name)
+    }
+
+    fn reload(&mut self, path: &Path) {
+        if let Some(dir) = path.parent() {
+            self.reload_dir(dir);
+        }
+    }
+
+    fn reload_dir(&mut self, dir: &Path) {
+        let dir = dir.to_path_buf();
+        let case_insensitive = self.roots
+            .iter()
+            .find(|root| dir.starts_with(&root.path))
+            .is_some_and(|root| root.case_insensitive);
+
+        // With both .gitignore and .ignore in one directory, the rules are combined in the
+        // order of the configured names.
+        let mut combined: Option<IgnoreFile> = None;
+        for name in &self.ignore_file_names {
+            if let Ok(file) = IgnoreFile::load(&dir.join(name), case_insensitive) {
+                match combined.as_mut() {
+                    Some(combined) => combined.rules.extend(file.rules),
+                    None => combined = Some(file),
+                }
+            }
+        }
+
+        match combined {
+            Some(file) => {
+                self.ignore_files.insert(dir, file);
+            }
+            None => {
+                self.ignore_files.remove(&dir);
+            }
+        }
+    }
+
+    fn compile(&mut self) {
+        for root in &mut self.roots {
+            root.case_insensitive = match self.case_sensitivity {
+                CaseSensitivity::Sensitive => false,
+                CaseSensitivity::Insensitive => true,
+                CaseSensitivity::Auto => is_case_insensitive(&root.path),
+            };
+        }
+
+        self.compiled = self.roots
+            .iter()
+            .map(|root| {
+                let compile = |patterns: &Vec<String>| -> Vec<Rule> {
+                    patterns.iter().filter_map(|pattern| Rule::parse(pattern, root.case_insensitive)).collect()
+                };
+                (compile(&self.include), compile(&self.exclude))
+            })
+            .collect();
+
+        let loaded: Vec<PathBuf> = self.ignore_files.values().map(|file| file.path.clone()).collect();
+        for path in loaded {
+            self.reload(&path);
+        }
+    }
+}
+
+fn last_match(rules: &[Rule], relative: &str, is_dir: bool) -> Option<bool> {
+    rules
+        .iter()
+        .rev()
+        .find(|rule| rule.matches(relative, is_dir))
+        .map(|rule| !rule.negated)
+}
+
+fn relative_text(path: &Path, base: &Path, case_insensitive: bool) -> String {
+    let relative = path
+        .strip_prefix(base)
+        .unwrap_or(path)
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+
+    if case_insensitive {
+        relative.to_lowercase()
+    } else {
+        relative
+    }
+}
+
+// Looks the root up again under a name that differs only in case: on a case-insensitive
+// file system it is the same entry. Roots without letters fall back to the platform's
+// usual default.
+fn is_case_insensitive(root: &Path) -> bool {
+    let probe = root.ancestors().find_map(|path| {
+        let name = path.file_name()?.to_str()?;
+        let swapped: String = name
+            .chars()
+            .map(|character| if character.is_lowercase() { character.to_ascii_uppercase() } else { character.to_ascii_lowercase() })
+            .collect();
+        (swapped != name).then(|| (path.to_path_buf(), path.with_file_name(swapped)))
+    });
+
+    match probe {
+        Some((original, swapped)) => match (fs::metadata(&original), fs::metadata(&swapped)) {
+            (Ok(original), Ok(swapped)) => original.dev() == swapped.dev() && original.ino() == swapped.ino(),
+            _ => false,
+        },
+        None => cfg!(target_os = "macos"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TestDir;
+
+    fn file(path: &Path, id: u64) -> FsEvent {
+        FsEvent::new(path, FSEventStreamEventFlags::ITEM_MODIFIED | FSEventStreamEventFlags::ITEM_IS_FILE, id)
+    }
+
+    #[test]
+    fn test_glob() {
+        assert!(Glob::new("*.rs").matches("main.rs"));
+        assert!(!Glob::new("*.rs").matches("src/main.rs"));
+        assert!(Glob::new("src/**/*.rs").matches("src/main.rs"));
+        assert!(Glob::new("src/**/*.rs").matches("src/a/b/main.rs"));
+        assert!(Glob::new("target/**").matches("target/debug/build"));
+        assert!(Glob::new("file?.[ch]").matches("file1.c"));
+        assert!(!Glob::new("file?.[!ch]").matches("file1.c"));
+        assert!(Glob::new("[a-c]x").matches("bx"));
+        assert!(Glob::new("\\*").matches("*"));
+    }
+
+    #[test]
+    fn test_rules() {
+        let rule = |line| Rule::parse(line, false).unwrap();
+        assert!(rule("*.log").matches("a/b/debug.log", false));
+        assert!(!rule("/debug.log").matches("a/debug.log", false));
+        assert!(rule("build/").matches("a/build", true));
+        assert!(!rule("build/").matches("a/build", false));
+        assert!(rule("!keep.log").negated);
+        assert_eq!(Rule::parse("# comment", false), None);
+        assert_eq!(Rule::parse("   ", false), None);
+    }
+
+    #[test]
+    fn test_include_and_exclude() {
+        let dir = TestDir::new("filter_globs");
+        let filter = EventFilter::new(&[dir.path()])
+            .with_case_sensitivity(CaseSensitivity::Sensitive)
+            .include("*.rs")
+            .include("Cargo.toml")
+            .exclude("target/");
+
+        assert!(!filter.is_ignored(&dir.path().join("src/main.rs"), false));
+        assert!(filter.is_ignored(&dir.path().join("src/notes.txt"), false));
+        assert!(!filter.is_ignored(&dir.path().join("src"), true));
+        assert!(filter.is_ignored(&dir.path().join("target"), true));
+        assert!(filter.is_ignored(&dir.path().join("target/build.rs"), false));
+        assert!(!filter.is_ignored(Path::new("/elsewhere/notes.txt"), false));
+    }
+
+    #[test]
+    fn test_nested_ignore_files() {
+        let dir = TestDir::new("filter_nested");
+        fs::create_dir_all(dir.path().join("sub/logs")).unwrap();
+        fs::write(dir.path().join(".gitignore"), "*.log\nlogs/\n").unwrap();
+        fs::write(dir.path().join("sub/.gitignore"), "!keep.log\n").unwrap();
+
+        let mut filter = EventFilter::new(&[dir.path()]).with_case_sensitivity(CaseSensitivity::Sensitive);
+        filter.load_ignore_files().unwrap();
+        assert_eq!(filter.ignore_files().count(), 2);
+
+        assert!(filter.is_ignored(&dir.path().join("debug.log"), false));
+        assert!(filter.is_ignored(&dir.path().join("sub/debug.log"), false));
+        assert!(!filter.is_ignored(&dir.path().join("sub/keep.log"), false));
+        // Nothing is re-included below an ignored directory.
+        assert!(filter.is_ignored(&dir.path().join("sub/logs/keep.log"), false));
+        assert!(!filter.is_ignored(&dir.path().join("sub/main.rs"), false));
+    }
+
+    #[test]
+    fn test_ignore_file_changes_apply_live() {
+        let dir = TestDir::new("filter_live");
+        let ignore = dir.path().join(".ignore");
+        let cache = dir.path().join("cache.bin");
+
+        let mut filter = EventFilter::new(&[dir.path()]).with_case_sensitivity(CaseSensitivity::Sensitive);
+        assert_eq!(filter.process(vec![file(&cache, 1)]).len(), 1);
+
+        fs::write(&ignore, "*.bin\n").unwrap();
+        let events = filter.process(vec![
+            FsEvent::new(&ignore, FSEventStreamEventFlags::ITEM_CREATED | FSEventStreamEventFlags::ITEM_IS_FILE, 2),
+            file(&cache, 3),
+        ]);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].id, 2);
+
+        fs::remove_file(&ignore).unwrap();
+        filter.process(vec![FsEvent::new(&ignore, FSEventStreamEventFlags::ITEM_REMOVED | FSEventStreamEventFlags::ITEM_IS_FILE, 4)]);
+        assert_eq!(filter.process(vec![file(&cache, 5)]).len(), 1);
+    }
+
+    #[test]
+    fn test_directory_events_reload_ignore_files() {
+        let dir = TestDir::new("filter_directories");
+        let sub = dir.path().join("sub");
+        fs::create_dir(&sub).unwrap();
+        let cache = sub.join("cache.bin");
+
+        let mut filter = EventFilter::new(&[dir.path()]).with_case_sensitivity(CaseSensitivity::Sensitive);
+
+        // Without file events only the directory is reported.
+        fs::write(sub.join(".gitignore"), "*.bin\n").unwrap();
+        filter.process(vec![FsEvent::new(&sub, FSEventStreamEventFlags::NONE, 1)]);
+        assert!(filter.is_ignored(&cache, false));
+
+        let moved = dir.path().join("moved");
+        fs::rename(&sub, &moved).unwrap();
+        filter.process(vec![
+            FsEvent::new(&sub, FSEventStreamEventFlags::ITEM_RENAMED | FSEventStreamEventFlags::ITEM_IS_DIR, 2),
+            FsEvent::new(&moved, FSEventStreamEventFlags::ITEM_RENAMED | FSEventStreamEventFlags::ITEM_IS_DIR, 3),
+        ]);
+        assert!(!filter.is_ignored(&cache, false));
+        assert!(filter.is_ignored(&moved.join("cache.bin"), false));
+
+        fs::remove_file(moved.join(".gitignore")).unwrap();
+        filter.process(vec![FsEvent::new("/", FSEventStreamEventFlags::MUST_SCAN_SUB_DIRS | FSEventStreamEventFlags::KERNEL_DROPPED, 4)]);
+        assert_eq!(filter.ignore_files().count(), 0);
+    }
+
+    #[test]
+    fn test_glob_does_not_backtrack_exponentially() {
+        let text = "a".repeat(200);
+        assert!(!Glob::new("*a*a*a*a*a*a*a*a*a*a*b").matches(&text));
+        assert!(!Glob::new("**a**a**a**a**a**a**a**a**b").matches(&text));
+        assert!(Glob::new("*a*a*a*a*a*a*a*a*a*a").matches(&text));
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        let dir = TestDir::new("filter_case");
+        let filter = EventFilter::new(&[dir.path()])
+            .with_case_sensitivity(CaseSensitivity::Insensitive)
+            .exclude("*.JPG");
+
+        assert!(filter.is_ignored(&dir.path().join("photo.jpg"), false));
+        assert!(filter.is_ignored(&dir.path().join("Photo.Jpg"), false));
+
+        let filter = EventFilter::new(&[dir.path()])
+            .with_case_sensitivity(CaseSensitivity::Sensitive)
+            .exclude("*.JPG");
+        assert!(!filter.is_ignored(&dir.path().join("photo.jpg"), false));
+    }
+}
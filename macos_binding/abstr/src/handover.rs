@@ -0,0 +1,141 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use crate::event::{FSEventStreamEventFlags, FSEventStreamEventId, FsEvent};
+
+pub type Generation = u64;
+
+// Joins the events of a stream and of the one replacing it into one sequence. FSEvents
+// cannot change the paths of a stream, so a new one is started Since(the latest id of the
+// old one) and the old one is only stopped after; in between both deliver, and the new
+// one replays what the old one may already have delivered. Event ids are global, so an
+// id and path seen once during that overlap is dropped the second time. Once the new
+// stream has finished replaying (HISTORY_DONE) it has everything, and what the old one
+// still delivers is dropped.
+#[derive(Debug, Default)]
+pub struct StreamHandover {
+    generation: Generation,
+    since: Option<FSEventStreamEventId>,
+    previous_live: bool,
+    removed: Vec<PathBuf>,
+    delivered: HashSet<(FSEventStreamEventId, PathBuf)>,
+}
+
+impl StreamHandover {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn generation(&self) -> Generation {
+        self.generation
+    }
+
+    pub fn is_overlapping(&self) -> bool {
+        self.since.is_some()
+    }
+
+    // Called before the replacing stream is started; its events are to be passed with
+    // the returned generation. `removed` are the roots the new stream no longer watches,
+    // spelled the way the stream reports paths.
+    pub fn restart(&mut self, since: FSEventStreamEventId, removed: Vec<PathBuf>) -> Generation {
+        self.generation += 1;
+        self.since = Some(since);
+        self.previous_live = true;
+        self.removed = removed;
+        self.delivered.clear();
+        self.generation
+    }
+
+    pub fn process(&mut self, generation: Generation, events: Vec<FsEvent>) -> Vec<FsEvent> {
+        let since = match self.since {
+            Some(since) => since,
+            None if generation == self.generation => return events,
+            None => return Vec::new(),
+        };
+
+        let current = generation == self.generation;
+        if !current && (!self.previous_live || generation + 1 != self.generation) {
+            return Vec::new();
+        }
+
+        let mut kept = Vec::with_capacity(events.len());
+        for event in events {
+            if current && event.flags.contains(FSEventStreamEventFlags::HISTORY_DONE) {
+                // Only asked for to close the gap; consumers did not.
+                self.finish();
+                continue;
+            }
+
+            if !current && self.is_removed(&event.path) {
+                continue;
+            }
+
+            if event.id > since && !self.delivered.insert((event.id, event.path.clone())) {
+                continue;
+            }
+
+            kept.push(event);
+        }
+        kept
+    }
+
+    fn finish(&mut self) {
+        self.since = None;
+        self.previous_live = false;
+        self.removed.clear();
+        self.delivered.clear();
+    }
+
+    fn is_removed(&self, path: &Path) -> bool {
+        self.removed.iter().any(|root| path.starts_with(root))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(path: &str, id: FSEventStreamEventId) -> FsEvent {
+        FsEvent::new(path, FSEventStreamEventFlags::ITEM_MODIFIED, id)
+    }
+
+    fn history_done(id: FSEventStreamEventId) -> FsEvent {
+        FsEvent::new("", FSEventStreamEventFlags::HISTORY_DONE, id)
+    }
+
+    #[test]
+    fn test_overlap_deduplicated() {
+        let mut handover = StreamHandover::new();
+        assert_eq!(handover.process(0, vec![event("/w/a", 10)]), vec![event("/w/a", 10)]);
+
+        let generation = handover.restart(10, Vec::new());
+        assert!(handover.is_overlapping());
+
+        // The old stream is still delivering while the new one replays from id 10.
+        assert_eq!(handover.process(0, vec![event("/w/b", 11)]), vec![event("/w/b", 11)]);
+        assert_eq!(
+            handover.process(generation, vec![event("/w/b", 11), event("/w/c", 12), history_done(12)]),
+            vec![event("/w/c", 12)]
+        );
+        assert!(!handover.is_overlapping());
+
+        // Late deliveries of the old stream are covered by the new one.
+        assert!(handover.process(0, vec![event("/w/c", 12)]).is_empty());
+        assert_eq!(handover.process(generation, vec![event("/w/d", 13)]), vec![event("/w/d", 13)]);
+    }
+
+    #[test]
+    fn test_removed_roots_and_retired_streams() {
+        let mut handover = StreamHandover::new();
+        let first = handover.restart(5, vec![PathBuf::from("/w/gone")]);
+        assert_eq!(
+            handover.process(0, vec![event("/w/gone/file", 6), event("/w/kept", 7)]),
+            vec![event("/w/kept", 7)]
+        );
+
+        // A second restart before the first finished retires the original stream.
+        let second = handover.restart(7, Vec::new());
+        assert!(handover.process(0, vec![event("/w/kept", 8)]).is_empty());
+        assert_eq!(handover.process(first, vec![event("/w/kept", 8)]), vec![event("/w/kept", 8)]);
+        assert!(handover.process(second, vec![event("/w/kept", 8)]).is_empty());
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{self, Metadata};
+use std::io;
+use std::ops::Bound;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use crate::event::{FSEventStreamEventFlags, FsEvent};
+use crate::snapshot::visit_tree;
+
+type InodeKey = (u64, u64);
+
+const SHARED_CHANGES: FSEventStreamEventFlags = FSEventStreamEventFlags::ITEM_MODIFIED
+    .union(FSEventStreamEventFlags::ITEM_INODE_META_MOD)
+    .union(FSEventStreamEventFlags::ITEM_CHANGE_OWNER)
+    .union(FSEventStreamEventFlags::ITEM_XATTR_MOD)
+    .union(FSEventStreamEventFlags::ITEM_FINDER_INFO_MOD);
+
+struct Inode {
+    paths: BTreeSet<PathBuf>,
+    nlink: u64,
+}
+
+// Knows which paths below the watched roots share an inode, so every backend can set
+// ITEM_IS_HARDLINK on events for files with more than one link and
+// ITEM_IS_LAST_HARDLINK when a removal leaves a single link behind, like FSEvents does.
+// Links outside the roots are counted through the link count but never reported.
+pub struct HardlinkIndex {
+    roots: Vec<PathBuf>,
+    inodes: HashMap<InodeKey, Inode>,
+    paths: BTreeMap<PathBuf, InodeKey>,
+    sibling_events: bool,
+}
+
+impl HardlinkIndex {
+    pub fn new<P>(roots: &[P]) -> io::Result<Self> where P: AsRef<Path> {
+        let mut index = HardlinkIndex {
+            roots: roots.iter().map(|root| root.as_ref().to_path_buf()).collect(),
+            inodes: HashMap::new(),
+            paths: BTreeMap::new(),
+            sibling_events: false,
+        };
+
+        for root in roots {
+            visit_tree(root.as_ref(), &mut |path, metadata| index.insert(path, metadata))?;
+        }
+
+        Ok(index)
+    }
+
+    // A change to the content or metadata of one link is a change to all of them; with
+    // this enabled the other links inside the roots get an event of their own.
+    pub fn with_sibling_events(mut self, enabled: bool) -> Self {
+        self.sibling_events = enabled;
+        self
+    }
+
+    pub fn links(&self, path: &Path) -> Vec<&Path> {
+        match self.paths.get(path).and_then(|key| self.inodes.get(key)) {
+            Some(inode) => inode.paths.iter().map(|path| path.as_path()).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn process(&mut self, events: Vec<FsEvent>) -> Vec<FsEvent> {
+        let mut processed = Vec::with_capacity(events.len());
+
+        for mut event in events {
+            if !self.roots.iter().any(|root| event.path.starts_with(root)) {
+                processed.push(event);
+                continue;
+            }
+
+            let siblings = match fs::symlink_metadata(&event.path) {
+                Ok(metadata) if metadata.is_file() => self.present(&mut event, &metadata),
+                Ok(_) => {
+                    self.remove(&event.path);
+                    Vec::new()
+                }
+                Err(_) => {
+                    self.gone(&mut event);
+                    Vec::new()
+                }
+            };
+
+            processed.push(event);
+            processed.extend(siblings);
+        }
+
+        processed
+    }
+
+    fn present(&mut self, event: &mut FsEvent, metadata: &Metadata) -> Vec<FsEvent> {
+        self.insert(&event.path, metadata);
+        if metadata.nlink() < 2 {
+            return Vec::new();
+        }
+
+        event.flags |= FSEventStreamEventFlags::ITEM_IS_HARDLINK;
+
+        let changes = event.flags & SHARED_CHANGES;
+        if !self.sibling_events || changes.is_empty() {
+            return Vec::new();
+        }
+
+        let flags = changes | FSEventStreamEventFlags::ITEM_IS_FILE | FSEventStreamEventFlags::ITEM_IS_HARDLINK;
+        self.links(&event.path)
+            .into_iter()
+            .filter(|sibling| *sibling != event.path)
+            .map(|sibling| FsEvent::new(sibling, flags, event.id))
+            .collect()
+    }
+
+    fn gone(&mut self, event: &mut FsEvent) {
+        let key = match self.paths.get(&event.path) {
+            Some(key) => *key,
+            None => {
+                // A directory that went away takes the links below it along.
+                self.remove(&event.path);
+                return;
+            }
+        };
+
+        let inode = &self.inodes[&key];
+        let nlink = inode.nlink;
+        let siblings: Vec<PathBuf> = inode.paths.iter().filter(|path| **path != event.path).cloned().collect();
+        self.remove(&event.path);
+
+        if nlink < 2 {
+            return;
+        }
+
+        let remaining = siblings
+            .iter()
+            .find_map(|path| fs::symlink_metadata(path).ok())
+            .map(|metadata| metadata.nlink())
+            .unwrap_or(nlink - 1);
+        if let Some(inode) = self.inodes.get_mut(&key) {
+            inode.nlink = remaining;
+        }
+
+        event.flags |= FSEventStreamEventFlags::ITEM_IS_HARDLINK;
+        if remaining <= 1 {
+            event.flags |= FSEventStreamEventFlags::ITEM_IS_LAST_HARDLINK;
+        }
+    }
+
+    fn insert(&mut self, path: &Path, metadata: &Metadata) {
+        if !metadata.is_file() {
+            return;
+        }
+
+        let key = (metadata.dev(), metadata.ino());
+        if self.paths.get(path) != Some(&key) {
+            self.remove(path);
+        }
+
+        self.paths.insert(path.to_path_buf(), key);
+        let inode = self.inodes.entry(key).or_insert_with(|| Inode { paths: BTreeSet::new(), nlink: 0 });
+        inode.paths.insert(path.to_path_buf());
+        inode.nlink = metadata.nlink();
+    }
+
+    fn remove(&mut self, path: &Path) {
+        let removed: Vec<PathBuf> = self.paths
+            .range::<Path, _>((Bound::Included(path), Bound::Unbounded))
+            .take_while(|(known, _)| known.starts_with(path))
+            .map(|(known, _)| known.clone())
+            .collect();
+
+        for known in removed {
+            let key = self.paths.remove(&known).unwrap();
+            if let Some(inode) = self.inodes.get_mut(&key) {
+                inode.paths.remove(&known);
+                if inode.paths.is_empty() {
+                    self.inodes.remove(&key);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TestDir;
+
+    const FILE: FSEventStreamEventFlags = FSEventStreamEventFlags::ITEM_IS_FILE;
+
+    #[test]
+    fn test_hardlink_flags() {
+        let dir = TestDir::new("hardlink_flags");
+        let first = dir.path().join("first");
+        let second = dir.path().join("second");
+        fs::write(&first, "a").unwrap();
+
+        let mut index = HardlinkIndex::new(&[dir.path()]).unwrap();
+
+        fs::hard_link(&first, &second).unwrap();
+        let events = index.process(vec![FsEvent::new(&second, FSEventStreamEventFlags::ITEM_CREATED | FILE, 1)]);
+        assert!(events[0].flags.contains(FSEventStreamEventFlags::ITEM_IS_HARDLINK));
+        assert_eq!(index.links(&first), vec![first.as_path(), second.as_path()]);
+
+        fs::remove_file(&second).unwrap();
+        let events = index.process(vec![FsEvent::new(&second, FSEventStreamEventFlags::ITEM_REMOVED | FILE, 2)]);
+        assert!(events[0].flags.contains(FSEventStreamEventFlags::ITEM_IS_HARDLINK | FSEventStreamEventFlags::ITEM_IS_LAST_HARDLINK));
+
+        let events = index.process(vec![FsEvent::new(&first, FSEventStreamEventFlags::ITEM_MODIFIED | FILE, 3)]);
+        assert!(!events[0].flags.intersects(FSEventStreamEventFlags::ITEM_IS_HARDLINK | FSEventStreamEventFlags::ITEM_IS_LAST_HARDLINK));
+    }
+
+    #[test]
+    fn test_link_outside_roots_counts() {
+        let outside = TestDir::new("hardlink_outside");
+        let dir = TestDir::new("hardlink_watched");
+        let file = dir.path().join("file");
+        fs::write(&file, "a").unwrap();
+        fs::hard_link(&file, outside.path().join("link")).unwrap();
+
+        let mut index = HardlinkIndex::new(&[dir.path()]).unwrap().with_sibling_events(true);
+        let events = index.process(vec![FsEvent::new(&file, FSEventStreamEventFlags::ITEM_MODIFIED | FILE, 1)]);
+        assert_eq!(events, vec![
+            FsEvent::new(&file, FSEventStreamEventFlags::ITEM_MODIFIED | FILE | FSEventStreamEventFlags::ITEM_IS_HARDLINK, 1),
+        ]);
+    }
+
+    #[test]
+    fn test_sibling_events() {
+        let dir = TestDir::new("hardlink_siblings");
+        let first = dir.path().join("first");
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        let second = dir.path().join("sub/second");
+        fs::write(&first, "a").unwrap();
+        fs::hard_link(&first, &second).unwrap();
+
+        let mut index = HardlinkIndex::new(&[dir.path()]).unwrap();
+        let modified = FsEvent::new(&first, FSEventStreamEventFlags::ITEM_MODIFIED | FILE, 1);
+        assert_eq!(index.process(vec![modified.clone()]).len(), 1);
+
+        let mut index = index.with_sibling_events(true);
+        assert_eq!(index.process(vec![modified]), vec![
+            FsEvent::new(&first, FSEventStreamEventFlags::ITEM_MODIFIED | FILE | FSEventStreamEventFlags::ITEM_IS_HARDLINK, 1),
+            FsEvent::new(&second, FSEventStreamEventFlags::ITEM_MODIFIED | FILE | FSEventStreamEventFlags::ITEM_IS_HARDLINK, 1),
+        ]);
+
+        fs::remove_dir_all(dir.path().join("sub")).unwrap();
+        index.process(vec![FsEvent::new(dir.path().join("sub"), FSEventStreamEventFlags::ITEM_REMOVED | FSEventStreamEventFlags::ITEM_IS_DIR, 2)]);
+        assert_eq!(index.links(&first), vec![first.as_path()]);
+    }
+}
@@ -1 +1,22 @@
-pub mod utils;
\ No newline at end of file
+pub mod utils;
+pub mod event;
+pub mod snapshot;
+pub mod rescan;
+pub mod change_set;
+pub mod atomic_save;
+pub mod stability;
+pub mod enrich;
+pub mod attrib;
+pub mod hardlink;
+pub mod root;
+pub mod self_events;
+pub mod process;
+pub mod access;
+pub mod permission;
+pub mod filter;
+pub mod exclusion;
+pub mod handover;
+pub mod sync;
+pub mod watch_set;
+#[cfg(target_os = "linux")]
+pub mod mounts;
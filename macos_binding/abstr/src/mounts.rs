@@ -0,0 +1,259 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use crate::event::{FSEventStreamEventFlags, FsEvent};
+
+const MOUNTINFO: &str = "/proc/self/mountinfo";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MountInfo {
+    pub id: u32,
+    pub parent_id: u32,
+    pub mount_point: PathBuf,
+    pub fs_type: String,
+    pub source: String,
+}
+
+impl MountInfo {
+    // 36 35 98:0 /mnt1 /mnt/parent rw,noatime master:1 - ext3 /dev/root rw,errors=continue
+    pub fn parse_line(line: &str) -> Option<Self> {
+        let (mount, filesystem) = line.split_once(" - ")?;
+        let mut mount = mount.split(' ');
+        let mut filesystem = filesystem.split(' ');
+
+        let id = mount.next()?.parse().ok()?;
+        let parent_id = mount.next()?.parse().ok()?;
+        let mount_point = unescape(mount.nth(2)?);
+
+        Some(MountInfo {
+            id,
+            parent_id,
+            mount_point,
+            fs_type: filesystem.next()?.to_string(),
+            source: filesystem.next()?.to_string(),
+        })
+    }
+}
+
+pub fn parse_mountinfo(table: &str) -> Vec<MountInfo> {
+    table.lines().filter_map(MountInfo::parse_line).collect()
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MountUpdate {
+    pub events: Vec<FsEvent>,
+    // Paths the backend should start or stop watching because of the change.
+    pub watch: Vec<PathBuf>,
+    pub unwatch: Vec<PathBuf>,
+}
+
+impl MountUpdate {
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty() && self.watch.is_empty() && self.unwatch.is_empty()
+    }
+}
+
+// Follows /proc/self/mountinfo and reports file systems mounted or unmounted at or below
+// the watched roots as MOUNT/UNMOUNT events, as FSEvents does for volumes on macOS.
+//
+// By default the watch set stays on the file systems it started on: a new mount point is
+// handed back to be unwatched and watched again once it is unmounted. With
+// `follow_mounts` the watch set is extended across new mounts instead.
+pub struct MountWatcher {
+    roots: Vec<PathBuf>,
+    mounts: BTreeMap<u32, MountInfo>,
+    follow_mounts: bool,
+    file: File,
+}
+
+impl MountWatcher {
+    pub fn new<P>(roots: &[P]) -> io::Result<Self> where P: AsRef<Path> {
+        let mut watcher = MountWatcher {
+            roots: roots.iter().map(|root| root.as_ref().to_path_buf()).collect(),
+            mounts: BTreeMap::new(),
+            follow_mounts: false,
+            file: File::open(MOUNTINFO)?,
+        };
+
+        let table = watcher.read_table()?;
+        watcher.mounts = parse_mountinfo(&table).into_iter().map(|mount| (mount.id, mount)).collect();
+        Ok(watcher)
+    }
+
+    pub fn with_follow_mounts(mut self, follow_mounts: bool) -> Self {
+        self.follow_mounts = follow_mounts;
+        self
+    }
+
+    // Mounts at or below the watched roots.
+    pub fn mounts(&self) -> Vec<&MountInfo> {
+        self.mounts.values().filter(|mount| self.is_watched(&mount.mount_point)).collect()
+    }
+
+    // The kernel flags the mount table with POLLPRI whenever it changes. Returns an empty
+    // update when nothing changed before the timeout; `None` waits indefinitely.
+    pub fn wait(&mut self, timeout: Option<Duration>) -> io::Result<MountUpdate> {
+        let mut poll_fd = libc::pollfd { fd: self.file.as_raw_fd(), events: libc::POLLPRI, revents: 0 };
+        let timeout = timeout.map(|timeout| timeout.as_millis().min(i32::MAX as u128) as i32).unwrap_or(-1);
+
+        let ready = unsafe { libc::poll(&mut poll_fd, 1, timeout) };
+        if ready < 0 {
+            let error = io::Error::last_os_error();
+            return if error.kind() == io::ErrorKind::Interrupted { Ok(MountUpdate::default()) } else { Err(error) };
+        }
+        if ready == 0 {
+            return Ok(MountUpdate::default());
+        }
+
+        self.refresh()
+    }
+
+    pub fn refresh(&mut self) -> io::Result<MountUpdate> {
+        let table = self.read_table()?;
+        Ok(self.apply(&table))
+    }
+
+    pub fn apply(&mut self, table: &str) -> MountUpdate {
+        let current: BTreeMap<u32, MountInfo> = parse_mountinfo(table).into_iter().map(|mount| (mount.id, mount)).collect();
+        let mut update = MountUpdate::default();
+
+        for (id, mount) in &self.mounts {
+            if !current.contains_key(id) && self.is_watched(&mount.mount_point) {
+                update.events.push(FsEvent::new(&mount.mount_point, FSEventStreamEventFlags::UNMOUNT, 0));
+                if self.follow_mounts {
+                    update.unwatch.push(mount.mount_point.clone());
+                } else {
+                    update.watch.push(mount.mount_point.clone());
+                }
+            }
+        }
+
+        for (id, mount) in &current {
+            if !self.mounts.contains_key(id) && self.is_watched(&mount.mount_point) {
+                update.events.push(FsEvent::new(&mount.mount_point, FSEventStreamEventFlags::MOUNT, 0));
+                if self.follow_mounts {
+                    update.watch.push(mount.mount_point.clone());
+                } else {
+                    update.unwatch.push(mount.mount_point.clone());
+                }
+            }
+        }
+
+        self.mounts = current;
+        update
+    }
+
+    fn is_watched(&self, path: &Path) -> bool {
+        self.roots.iter().any(|root| path.starts_with(root))
+    }
+
+    fn read_table(&mut self) -> io::Result<String> {
+        let mut table = String::new();
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.read_to_string(&mut table)?;
+        Ok(table)
+    }
+}
+
+// Mount points have spaces, tabs, newlines and backslashes escaped as octal.
+fn unescape(value: &str) -> PathBuf {
+    let bytes = value.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        let octal = bytes.get(index + 1..index + 4)
+            .filter(|_| bytes[index] == b'\\')
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+
+        match octal {
+            Some(byte) => {
+                unescaped.push(byte);
+                index += 4;
+            }
+            None => {
+                unescaped.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+
+    PathBuf::from(OsString::from_vec(unescaped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TABLE: &str = "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+23 22 0:21 / /proc rw,nosuid shared:2 - proc proc rw
+";
+
+    fn watcher(roots: &[&str], table: &str) -> MountWatcher {
+        let mut watcher = MountWatcher::new(roots).unwrap();
+        watcher.mounts = parse_mountinfo(table).into_iter().map(|mount| (mount.id, mount)).collect();
+        watcher
+    }
+
+    #[test]
+    fn test_parse_line() {
+        let mount = MountInfo::parse_line("36 35 98:0 /mnt1 /mnt/my\\040disk rw,noatime master:1 - ext3 /dev/root rw,errors=continue").unwrap();
+        assert_eq!(mount, MountInfo {
+            id: 36,
+            parent_id: 35,
+            mount_point: PathBuf::from("/mnt/my disk"),
+            fs_type: "ext3".to_string(),
+            source: "/dev/root".to_string(),
+        });
+        assert_eq!(MountInfo::parse_line("garbage"), None);
+    }
+
+    #[test]
+    fn test_mount_and_unmount_below_roots() {
+        let mut watcher = watcher(&["/data"], TABLE);
+
+        let mounted = format!("{}{}", TABLE, "\
+40 22 8:17 / /data/usb rw - vfat /dev/sdb1 rw
+41 22 8:33 / /other rw - ext4 /dev/sdc1 rw
+");
+        assert_eq!(watcher.apply(&mounted), MountUpdate {
+            events: vec![FsEvent::new("/data/usb", FSEventStreamEventFlags::MOUNT, 0)],
+            watch: vec![],
+            unwatch: vec![PathBuf::from("/data/usb")],
+        });
+        assert_eq!(watcher.mounts().len(), 1);
+
+        assert_eq!(watcher.apply(TABLE), MountUpdate {
+            events: vec![FsEvent::new("/data/usb", FSEventStreamEventFlags::UNMOUNT, 0)],
+            watch: vec![PathBuf::from("/data/usb")],
+            unwatch: vec![],
+        });
+        assert!(watcher.apply(TABLE).is_empty());
+    }
+
+    #[test]
+    fn test_follow_mounts() {
+        let mut watcher = watcher(&["/data"], TABLE).with_follow_mounts(true);
+
+        let update = watcher.apply(&format!("{}{}", TABLE, "40 22 8:17 / /data/usb rw - vfat /dev/sdb1 rw\n"));
+        assert_eq!(update.watch, vec![PathBuf::from("/data/usb")]);
+        assert!(update.unwatch.is_empty());
+
+        let update = watcher.apply(TABLE);
+        assert_eq!(update.unwatch, vec![PathBuf::from("/data/usb")]);
+    }
+
+    #[test]
+    fn test_wait_times_out_without_changes() {
+        let mut watcher = MountWatcher::new(&["/"]).unwrap();
+        assert!(!watcher.mounts().is_empty());
+        assert!(watcher.wait(Some(Duration::from_millis(10))).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,253 @@
+use std::os::unix::io::OwnedFd;
+use std::path::PathBuf;
+
+const FAN_OPEN_PERM: u64 = 0x0001_0000;
+const FAN_ACCESS_PERM: u64 = 0x0002_0000;
+const FAN_OPEN_EXEC_PERM: u64 = 0x0004_0000;
+
+const FAN_ALLOW: u32 = 0x01;
+const FAN_DENY: u32 = 0x02;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PermissionKind {
+    Open,
+    Access,
+    OpenExec,
+}
+
+impl PermissionKind {
+    pub fn to_raw(self) -> u64 {
+        match self {
+            PermissionKind::Open => FAN_OPEN_PERM,
+            PermissionKind::Access => FAN_ACCESS_PERM,
+            PermissionKind::OpenExec => FAN_OPEN_EXEC_PERM,
+        }
+    }
+
+    pub fn from_raw(mask: u64) -> Option<Self> {
+        if mask & FAN_OPEN_EXEC_PERM != 0 {
+            Some(PermissionKind::OpenExec)
+        } else if mask & FAN_OPEN_PERM != 0 {
+            Some(PermissionKind::Open)
+        } else if mask & FAN_ACCESS_PERM != 0 {
+            Some(PermissionKind::Access)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    Deny,
+}
+
+impl Decision {
+    pub fn to_raw(self) -> u32 {
+        match self {
+            Decision::Allow => FAN_ALLOW,
+            Decision::Deny => FAN_DENY,
+        }
+    }
+}
+
+// What the kernel asks about, without the descriptor needed to answer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PermissionEvent {
+    pub path: PathBuf,
+    pub kind: PermissionKind,
+    pub pid: Option<u32>,
+}
+
+// An operation the kernel holds until it is answered. `fd` is the descriptor fanotify
+// passed with the event; it identifies the request in the response and is closed once
+// the request is answered.
+#[derive(Debug)]
+pub struct PermissionRequest {
+    pub event: PermissionEvent,
+    pub fd: OwnedFd,
+}
+
+#[cfg(target_os = "linux")]
+pub use self::linux::{respond, PermissionListener};
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::ffi::CString;
+    use std::fs;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+    use std::path::{Path, PathBuf};
+    use super::{Decision, PermissionEvent, PermissionKind, PermissionRequest};
+
+    const METADATA_LEN: usize = std::mem::size_of::<libc::fanotify_event_metadata>();
+
+    // Writes the answer to the fanotify group; the event's descriptor is closed with
+    // the request.
+    pub fn respond(fanotify_fd: BorrowedFd<'_>, request: PermissionRequest, decision: Decision) -> io::Result<()> {
+        let response = libc::fanotify_response { fd: request.fd.as_raw_fd(), response: decision.to_raw() };
+        let size = std::mem::size_of::<libc::fanotify_response>();
+        let written = unsafe {
+            libc::write(fanotify_fd.as_raw_fd(), &response as *const libc::fanotify_response as *const libc::c_void, size)
+        };
+        if written == size as isize { Ok(()) } else { Err(io::Error::last_os_error()) }
+    }
+
+    // A fanotify group that asks before files below its roots are opened, read or
+    // executed. Needs CAP_SYS_ADMIN. Whole mounts are marked, so what happens outside
+    // the roots is allowed right away without asking.
+    #[derive(Debug)]
+    pub struct PermissionListener {
+        fd: OwnedFd,
+        roots: Vec<PathBuf>,
+    }
+
+    impl PermissionListener {
+        pub fn new() -> io::Result<Self> {
+            let fd = unsafe {
+                libc::fanotify_init(libc::FAN_CLASS_CONTENT | libc::FAN_CLOEXEC, (libc::O_RDONLY | libc::O_LARGEFILE) as libc::c_uint)
+            };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(PermissionListener { fd: unsafe { OwnedFd::from_raw_fd(fd) }, roots: Vec::new() })
+        }
+
+        pub fn add_root<P>(&mut self, root: P, kinds: &[PermissionKind]) -> io::Result<()> where P: Into<PathBuf> {
+            let root = root.into();
+            let path = CString::new(root.as_os_str().as_bytes()).map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+            let mask = kinds.iter().fold(0, |mask, kind| mask | kind.to_raw());
+
+            let result = unsafe {
+                libc::fanotify_mark(self.fd.as_raw_fd(), libc::FAN_MARK_ADD | libc::FAN_MARK_MOUNT, mask, libc::AT_FDCWD, path.as_ptr())
+            };
+            if result != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            self.roots.push(root);
+            Ok(())
+        }
+
+        // Blocks until the kernel asks about something below a root.
+        pub fn read(&self) -> io::Result<Vec<PermissionRequest>> {
+            let mut buffer = vec![0u8; 64 * METADATA_LEN];
+            loop {
+                let size = unsafe { libc::read(self.fd.as_raw_fd(), buffer.as_mut_ptr() as *mut libc::c_void, buffer.len()) };
+                if size < 0 {
+                    let error = io::Error::last_os_error();
+                    if error.kind() == io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    return Err(error);
+                }
+
+                let mut requests = Vec::new();
+                for (mask, fd, pid) in parse_events(&buffer[..size as usize]) {
+                    // Owned right away so that every descriptor is closed, whatever happens to it.
+                    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+                    let Some(kind) = PermissionKind::from_raw(mask) else {
+                        continue;
+                    };
+
+                    let path = fs::read_link(format!("/proc/self/fd/{}", fd.as_raw_fd())).unwrap_or_default();
+                    let request = PermissionRequest {
+                        event: PermissionEvent { path, kind, pid },
+                        fd,
+                    };
+
+                    if self.is_below_root(&request.event.path) {
+                        requests.push(request);
+                    } else {
+                        respond(self.fd.as_fd(), request, Decision::Allow)?;
+                    }
+                }
+
+                if !requests.is_empty() {
+                    return Ok(requests);
+                }
+            }
+        }
+
+        pub fn respond(&self, request: PermissionRequest, decision: Decision) -> io::Result<()> {
+            respond(self.fd.as_fd(), request, decision)
+        }
+
+        pub fn roots(&self) -> &[PathBuf] {
+            &self.roots
+        }
+
+        fn is_below_root(&self, path: &Path) -> bool {
+            self.roots.iter().any(|root| path.starts_with(root))
+        }
+    }
+
+    // The mask, descriptor and pid of each event in what one read returned. Events
+    // without a descriptor (a full queue) have nothing to answer and are skipped.
+    pub(super) fn parse_events(buffer: &[u8]) -> Vec<(u64, RawFd, Option<u32>)> {
+        let mut events = Vec::new();
+        let mut offset = 0;
+
+        while buffer.len() - offset >= METADATA_LEN {
+            let metadata = unsafe {
+                std::ptr::read_unaligned(buffer[offset..].as_ptr() as *const libc::fanotify_event_metadata)
+            };
+            let event_len = metadata.event_len as usize;
+            if metadata.vers != libc::FANOTIFY_METADATA_VERSION || event_len < METADATA_LEN || event_len > buffer.len() - offset {
+                break;
+            }
+
+            if metadata.fd != libc::FAN_NOFD {
+                let pid = if metadata.pid > 0 { Some(metadata.pid as u32) } else { None };
+                events.push((metadata.mask, metadata.fd, pid));
+            }
+            offset += event_len;
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raw_kinds() {
+        for kind in [PermissionKind::Open, PermissionKind::Access, PermissionKind::OpenExec] {
+            assert_eq!(PermissionKind::from_raw(kind.to_raw()), Some(kind));
+        }
+        // FAN_OPEN_EXEC_PERM comes together with FAN_OPEN_PERM.
+        assert_eq!(PermissionKind::from_raw(FAN_OPEN_PERM | FAN_OPEN_EXEC_PERM), Some(PermissionKind::OpenExec));
+        assert_eq!(PermissionKind::from_raw(0x08), None);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_parse_events() {
+        fn metadata(mask: u64, fd: i32, pid: i32) -> Vec<u8> {
+            let metadata = libc::fanotify_event_metadata {
+                event_len: std::mem::size_of::<libc::fanotify_event_metadata>() as u32,
+                vers: libc::FANOTIFY_METADATA_VERSION,
+                reserved: 0,
+                metadata_len: std::mem::size_of::<libc::fanotify_event_metadata>() as u16,
+                mask,
+                fd,
+                pid,
+            };
+            let bytes = &metadata as *const libc::fanotify_event_metadata as *const u8;
+            unsafe { std::slice::from_raw_parts(bytes, std::mem::size_of_val(&metadata)) }.to_vec()
+        }
+
+        let mut buffer = metadata(FAN_OPEN_PERM, 7, 42);
+        buffer.extend(metadata(0x4000, libc::FAN_NOFD, 0));
+        buffer.extend(metadata(FAN_ACCESS_PERM, 8, 0));
+        // A truncated record is left alone.
+        buffer.extend(&metadata(FAN_OPEN_PERM, 9, 1)[..4]);
+
+        assert_eq!(linux::parse_events(&buffer), vec![(FAN_OPEN_PERM, 7, Some(42)), (FAN_ACCESS_PERM, 8, None)]);
+    }
+}
@@ -0,0 +1,150 @@
+use std::path::PathBuf;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    // Gone or not readable when the process already exited or belongs to someone else.
+    pub exe: Option<PathBuf>,
+    pub uid: Option<u32>,
+    pub cmdline: Vec<String>,
+}
+
+#[cfg(target_os = "linux")]
+pub use self::linux::{attribute, ProcessInfoCache};
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::io;
+    use std::os::unix::io::RawFd;
+    use std::path::Path;
+    use crate::event::FsEvent;
+    use super::ProcessInfo;
+
+    impl ProcessInfo {
+        // Reads what /proc knows about `pid` right now. The pid may have been reused by the
+        // time this runs; prefer `from_pidfd` when the backend has one.
+        pub fn from_pid(pid: u32) -> io::Result<Self> {
+            let dir = Path::new("/proc").join(pid.to_string());
+            let status = fs::read_to_string(dir.join("status"))?;
+
+            let uid = status
+                .lines()
+                .find_map(|line| line.strip_prefix("Uid:"))
+                .and_then(|uids| uids.split_whitespace().next())
+                .and_then(|uid| uid.parse().ok());
+
+            let cmdline = fs::read(dir.join("cmdline"))
+                .map(|cmdline| {
+                    cmdline
+                        .split(|byte| *byte == 0)
+                        .filter(|argument| !argument.is_empty())
+                        .map(|argument| String::from_utf8_lossy(argument).into_owned())
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            Ok(ProcessInfo {
+                pid,
+                exe: fs::read_link(dir.join("exe")).ok(),
+                uid,
+                cmdline,
+            })
+        }
+
+        // For FAN_REPORT_PIDFD: the pid comes from the descriptor and the process is checked
+        // to still be alive after /proc was read, so the information cannot belong to a
+        // later process that got the same pid.
+        pub fn from_pidfd(pidfd: RawFd) -> io::Result<Self> {
+            let fdinfo = fs::read_to_string(format!("/proc/self/fdinfo/{}", pidfd))?;
+            let pid = fdinfo
+                .lines()
+                .find_map(|line| line.strip_prefix("Pid:"))
+                .and_then(|pid| pid.trim().parse::<i64>().ok())
+                .filter(|pid| *pid > 0)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "process of pidfd exited"))?;
+
+            let info = Self::from_pid(pid as u32)?;
+
+            let alive = unsafe { libc::syscall(libc::SYS_pidfd_send_signal, pidfd, 0, std::ptr::null::<libc::siginfo_t>(), 0) };
+            if alive != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(info)
+        }
+    }
+
+    // Batches tend to hold many events from the same few processes.
+    #[derive(Debug, Default)]
+    pub struct ProcessInfoCache {
+        processes: HashMap<u32, Option<ProcessInfo>>,
+    }
+
+    impl ProcessInfoCache {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn get(&mut self, pid: u32) -> Option<ProcessInfo> {
+            self.processes
+                .entry(pid)
+                .or_insert_with(|| ProcessInfo::from_pid(pid).ok())
+                .clone()
+        }
+    }
+
+    // Resolves the pid of every event that has one, at delivery time.
+    pub fn attribute(events: Vec<FsEvent>) -> Vec<FsEvent> {
+        let mut cache = ProcessInfoCache::new();
+
+        events
+            .into_iter()
+            .map(|mut event| {
+                if let Some(pid) = event.pid {
+                    event.process = cache.get(pid);
+                }
+                event
+            })
+            .collect()
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::event::{FSEventStreamEventFlags, FsEvent};
+
+    #[test]
+    fn test_own_process() {
+        let info = ProcessInfo::from_pid(std::process::id()).unwrap();
+        assert_eq!(info.exe, std::env::current_exe().ok());
+        assert_eq!(info.uid, Some(unsafe { libc::getuid() }));
+        assert_eq!(info.cmdline, std::env::args().collect::<Vec<String>>());
+    }
+
+    #[test]
+    fn test_attribute() {
+        let events = attribute(vec![
+            FsEvent::new("/w/a", FSEventStreamEventFlags::ITEM_MODIFIED, 1).with_pid(std::process::id()),
+            FsEvent::new("/w/b", FSEventStreamEventFlags::ITEM_MODIFIED, 2),
+        ]);
+
+        assert_eq!(events[0].process.as_ref().map(|process| process.pid), Some(std::process::id()));
+        assert_eq!(events[1].process, None);
+    }
+
+    #[test]
+    fn test_from_pidfd() {
+        let pidfd = unsafe { libc::syscall(libc::SYS_pidfd_open, std::process::id(), 0) };
+        // pidfd_open needs Linux 5.3.
+        if pidfd < 0 {
+            return;
+        }
+
+        let info = ProcessInfo::from_pidfd(pidfd as i32).unwrap();
+        unsafe { libc::close(pidfd as i32) };
+        assert_eq!(info.pid, std::process::id());
+    }
+}
@@ -0,0 +1,178 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::event::{FSEventStreamEventFlags, FSEventStreamEventId, FsEvent};
+use crate::snapshot::Snapshot;
+
+const IN_Q_OVERFLOW: u32 = 0x4000;
+
+// inotify reports a full event queue as IN_Q_OVERFLOW without a watch or a name; every
+// root has to be rescanned, which is what a dropped event above all of them asks for.
+pub fn from_inotify_overflow(mask: u32, id: FSEventStreamEventId) -> Option<FsEvent> {
+    if mask & IN_Q_OVERFLOW == 0 {
+        return None;
+    }
+
+    Some(FsEvent::new("/", FSEventStreamEventFlags::KERNEL_DROPPED | FSEventStreamEventFlags::MUST_SCAN_SUB_DIRS, id))
+}
+
+// Keeps the last known state of every watched root so that "something changed under
+// here" notifications (MUST_SCAN_SUB_DIRS, dropped events, inotify queue overflows) can
+// be turned into the precise created/removed/modified events they stand for.
+pub struct Rescanner {
+    snapshots: Vec<Snapshot>,
+}
+
+impl Rescanner {
+    pub fn new<P>(roots: &[P]) -> io::Result<Self> where P: AsRef<Path> {
+        let snapshots = roots
+            .iter()
+            .map(|root| Snapshot::capture(root.as_ref()))
+            .collect::<io::Result<Vec<Snapshot>>>()?;
+
+        Ok(Rescanner { snapshots })
+    }
+
+    pub fn roots(&self) -> Vec<&Path> {
+        self.snapshots.iter().map(|snapshot| snapshot.root.as_path()).collect()
+    }
+
+    pub fn add_root<P>(&mut self, root: P) -> io::Result<()> where P: AsRef<Path> {
+        if self.snapshots.iter().any(|snapshot| snapshot.root == root.as_ref()) {
+            return Ok(());
+        }
+
+        self.snapshots.push(Snapshot::capture(root.as_ref())?);
+        Ok(())
+    }
+
+    pub fn remove_root(&mut self, root: &Path) {
+        self.snapshots.retain(|snapshot| snapshot.root != root);
+    }
+
+    pub fn process(&mut self, events: Vec<FsEvent>) -> Vec<FsEvent> {
+        let mut processed = Vec::with_capacity(events.len());
+
+        for event in events {
+            if event.flags.needs_rescan() {
+                for scope in self.scopes(&event.path) {
+                    processed.extend(self.snapshots[scope.0].rescan(&scope.1, event.id));
+                }
+                continue;
+            }
+
+            if let Some(index) = self.snapshots.iter().position(|snapshot| event.path.starts_with(&snapshot.root)) {
+                self.snapshots[index].refresh(&event.path);
+            }
+            processed.push(event);
+        }
+
+        processed
+    }
+
+    // A rescan request below a root only concerns that subtree; one above a root (for
+    // example "/" after dropped events) concerns the whole root.
+    fn scopes(&self, path: &Path) -> Vec<(usize, PathBuf)> {
+        self.snapshots
+            .iter()
+            .enumerate()
+            .filter_map(|(index, snapshot)| {
+                if path.starts_with(&snapshot.root) {
+                    Some((index, path.to_path_buf()))
+                } else if snapshot.root.starts_with(path) {
+                    Some((index, snapshot.root.clone()))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::event::FSEventStreamEventFlags;
+    use crate::utils::TestDir;
+
+    fn has_event(events: &[FsEvent], path: &Path, flags: FSEventStreamEventFlags) -> bool {
+        events.iter().any(|event| event.path == path && event.flags.contains(flags))
+    }
+
+    #[test]
+    fn test_rescan_subtree_synthesizes_events() {
+        let dir = TestDir::new("rescan_subtree");
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("sub/kept"), "a").unwrap();
+        fs::write(dir.path().join("sub/removed"), "a").unwrap();
+        fs::write(dir.path().join("sub/modified"), "a").unwrap();
+
+        let mut rescanner = Rescanner::new(&[dir.path()]).unwrap();
+
+        fs::remove_file(dir.path().join("sub/removed")).unwrap();
+        fs::write(dir.path().join("sub/modified"), "changed").unwrap();
+        fs::write(dir.path().join("sub/created"), "a").unwrap();
+
+        let events = rescanner.process(vec![
+            FsEvent::new(dir.path().join("sub"), FSEventStreamEventFlags::MUST_SCAN_SUB_DIRS, 7),
+        ]);
+
+        assert_eq!(events.len(), 3);
+        assert!(has_event(&events, &dir.path().join("sub/removed"), FSEventStreamEventFlags::ITEM_REMOVED | FSEventStreamEventFlags::ITEM_IS_FILE));
+        assert!(has_event(&events, &dir.path().join("sub/modified"), FSEventStreamEventFlags::ITEM_MODIFIED));
+        assert!(has_event(&events, &dir.path().join("sub/created"), FSEventStreamEventFlags::ITEM_CREATED));
+        assert!(events.iter().all(|event| event.id == 7));
+    }
+
+    #[test]
+    fn test_dropped_events_rescan_whole_root() {
+        let dir = TestDir::new("rescan_dropped");
+        let mut rescanner = Rescanner::new(&[dir.path()]).unwrap();
+
+        fs::create_dir(dir.path().join("new_dir")).unwrap();
+
+        let events = rescanner.process(vec![
+            FsEvent::new("/", FSEventStreamEventFlags::KERNEL_DROPPED | FSEventStreamEventFlags::MUST_SCAN_SUB_DIRS, 1),
+        ]);
+
+        assert_eq!(events, vec![
+            FsEvent::new(dir.path().join("new_dir"), FSEventStreamEventFlags::ITEM_CREATED | FSEventStreamEventFlags::ITEM_IS_DIR, 1),
+        ]);
+    }
+
+    #[test]
+    fn test_inotify_overflow_rescans_every_root() {
+        let first = TestDir::new("rescan_overflow_first");
+        let second = TestDir::new("rescan_overflow_second");
+        let mut rescanner = Rescanner::new(&[first.path()]).unwrap();
+        rescanner.add_root(second.path()).unwrap();
+        assert_eq!(rescanner.roots(), vec![first.path(), second.path()]);
+
+        fs::write(first.path().join("file"), "a").unwrap();
+        fs::write(second.path().join("file"), "a").unwrap();
+
+        assert_eq!(from_inotify_overflow(0x0100, 3), None);
+        let events = rescanner.process(vec![from_inotify_overflow(IN_Q_OVERFLOW, 3).unwrap()]);
+        assert_eq!(events.len(), 2);
+        assert!(has_event(&events, &first.path().join("file"), FSEventStreamEventFlags::ITEM_CREATED));
+        assert!(has_event(&events, &second.path().join("file"), FSEventStreamEventFlags::ITEM_CREATED));
+
+        rescanner.remove_root(second.path());
+        assert_eq!(rescanner.roots(), vec![first.path()]);
+    }
+
+    #[test]
+    fn test_reported_events_are_not_repeated_by_rescan() {
+        let dir = TestDir::new("rescan_reported");
+        let mut rescanner = Rescanner::new(&[dir.path()]).unwrap();
+
+        fs::write(dir.path().join("file"), "a").unwrap();
+        let created = FsEvent::new(dir.path().join("file"), FSEventStreamEventFlags::ITEM_CREATED | FSEventStreamEventFlags::ITEM_IS_FILE, 1);
+        assert_eq!(rescanner.process(vec![created.clone()]), vec![created]);
+
+        let events = rescanner.process(vec![
+            FsEvent::new(dir.path(), FSEventStreamEventFlags::MUST_SCAN_SUB_DIRS, 2),
+        ]);
+        assert!(events.is_empty());
+    }
+}
@@ -0,0 +1,221 @@
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use crate::event::{FSEventStreamEventFlags, FsEvent};
+
+type Identity = (u64, u64);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RootChange {
+    // The root or one of its ancestors was renamed, removed or replaced.
+    Changed,
+    // In follow-root mode: a directory appeared at the root path again and is watched
+    // from now on. Whatever was under the old root should be rescanned.
+    Reattached,
+}
+
+// Identities along the path to a root: every component as found with lstat from the root
+// up to "/", followed by what the root resolves to, so that swapping a symlink counts too.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Chain(Vec<Option<Identity>>);
+
+impl Chain {
+    fn capture(root: &Path) -> Self {
+        let mut identities: Vec<Option<Identity>> = root
+            .ancestors()
+            .map(|path| fs::symlink_metadata(path).ok().map(|metadata| (metadata.dev(), metadata.ino())))
+            .collect();
+        identities.push(fs::metadata(root).ok().filter(|metadata| metadata.is_dir()).map(|metadata| (metadata.dev(), metadata.ino())));
+        Chain(identities)
+    }
+
+    fn resolves(&self) -> bool {
+        self.0.last().copied().flatten().is_some()
+    }
+}
+
+// Emulates FSEventStreamCreateFlags::WATCH_ROOT for backends that only see changes inside
+// the directories they watch: the backend also watches `watch_paths` and hands the events
+// it gets for them to `process`, which turns them into ROOT_CHANGED.
+pub struct RootWatcher {
+    root: PathBuf,
+    attached: Chain,
+    observed: Chain,
+    follow_root: bool,
+    last_change: Option<RootChange>,
+}
+
+impl RootWatcher {
+    pub fn new<P>(root: P) -> Self where P: Into<PathBuf> {
+        let root = root.into();
+        let chain = Chain::capture(&root);
+
+        RootWatcher {
+            root,
+            attached: chain.clone(),
+            observed: chain,
+            follow_root: false,
+            last_change: None,
+        }
+    }
+
+    // Re-resolves the root whenever something appears at its path again, for directories
+    // that are swapped by rename or by pointing a symlink elsewhere.
+    pub fn with_follow_root(mut self, follow_root: bool) -> Self {
+        self.follow_root = follow_root;
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    // The root's ancestors, whose entries have to be watched to notice a rename or removal.
+    pub fn watch_paths(&self) -> Vec<&Path> {
+        self.root.ancestors().skip(1).collect()
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.observed == self.attached
+    }
+
+    pub fn take_change(&mut self) -> Option<RootChange> {
+        self.last_change.take()
+    }
+
+    pub fn check(&mut self) -> Option<RootChange> {
+        let current = Chain::capture(&self.root);
+        if current == self.observed {
+            return None;
+        }
+
+        self.observed = current.clone();
+        let change = if self.follow_root && current.resolves() {
+            self.attached = current;
+            RootChange::Reattached
+        } else {
+            RootChange::Changed
+        };
+
+        self.last_change = Some(change);
+        Some(change)
+    }
+
+    // The ancestors are watched recursively, so besides their own events the ones of
+    // everything else below them arrive too. Those only serve to notice root changes (a
+    // symlink renamed over the root is an event on a sibling) and are dropped; the ones on
+    // the root and below it are kept.
+    pub fn process(&mut self, events: Vec<FsEvent>) -> Vec<FsEvent> {
+        let mut processed = Vec::with_capacity(events.len());
+
+        for event in events {
+            let on_root = event.path == self.root;
+            if !on_root && event.path.starts_with(&self.root) {
+                processed.push(event);
+                continue;
+            }
+
+            let id = event.id;
+            if on_root {
+                processed.push(event);
+            }
+
+            if self.check().is_some() {
+                processed.push(FsEvent::new(&self.root, FSEventStreamEventFlags::ROOT_CHANGED, id));
+            }
+        }
+
+        processed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use crate::utils::TestDir;
+
+    fn root_changed(path: &Path, id: u64) -> FsEvent {
+        FsEvent::new(path, FSEventStreamEventFlags::ROOT_CHANGED, id)
+    }
+
+    #[test]
+    fn test_root_removed_and_ancestor_renamed() {
+        let dir = TestDir::new("root_removed");
+        let parent = dir.path().join("parent");
+        let root = parent.join("root");
+        fs::create_dir_all(&root).unwrap();
+
+        let mut watcher = RootWatcher::new(&root);
+        assert_eq!(watcher.watch_paths()[0], parent.as_path());
+
+        fs::rename(&parent, dir.path().join("moved")).unwrap();
+        let events = watcher.process(vec![
+            FsEvent::new(&parent, FSEventStreamEventFlags::ITEM_RENAMED | FSEventStreamEventFlags::ITEM_IS_DIR, 4),
+            FsEvent::new(root.join("file"), FSEventStreamEventFlags::ITEM_CREATED | FSEventStreamEventFlags::ITEM_IS_FILE, 5),
+        ]);
+        assert_eq!(events, vec![
+            root_changed(&root, 4),
+            FsEvent::new(root.join("file"), FSEventStreamEventFlags::ITEM_CREATED | FSEventStreamEventFlags::ITEM_IS_FILE, 5),
+        ]);
+        assert_eq!(watcher.take_change(), Some(RootChange::Changed));
+
+        // Not following: recreating the root is one more change but it stays detached.
+        fs::create_dir_all(&root).unwrap();
+        assert_eq!(watcher.check(), Some(RootChange::Changed));
+        assert!(!watcher.is_attached());
+        assert_eq!(watcher.check(), None);
+    }
+
+    #[test]
+    fn test_siblings_under_ancestor_dropped() {
+        let dir = TestDir::new("root_sibling");
+        let root = dir.path().join("root");
+        fs::create_dir(&root).unwrap();
+        fs::write(dir.path().join("sibling"), "a").unwrap();
+
+        let mut watcher = RootWatcher::new(&root);
+        let inside = FsEvent::new(root.join("file"), FSEventStreamEventFlags::ITEM_CREATED | FSEventStreamEventFlags::ITEM_IS_FILE, 2);
+        let events = watcher.process(vec![
+            FsEvent::new(dir.path().join("sibling"), FSEventStreamEventFlags::ITEM_MODIFIED | FSEventStreamEventFlags::ITEM_IS_FILE, 1),
+            inside.clone(),
+            FsEvent::new(dir.path().join("rootless"), FSEventStreamEventFlags::ITEM_CREATED | FSEventStreamEventFlags::ITEM_IS_DIR, 3),
+        ]);
+        assert_eq!(events, vec![inside]);
+        assert_eq!(watcher.take_change(), None);
+    }
+
+    #[test]
+    fn test_follow_root_after_recreate() {
+        let dir = TestDir::new("root_follow");
+        let root = dir.path().join("root");
+        fs::create_dir(&root).unwrap();
+
+        let mut watcher = RootWatcher::new(&root).with_follow_root(true);
+
+        fs::remove_dir(&root).unwrap();
+        assert_eq!(watcher.check(), Some(RootChange::Changed));
+
+        fs::create_dir(&root).unwrap();
+        let events = watcher.process(vec![FsEvent::new(&root, FSEventStreamEventFlags::ITEM_CREATED | FSEventStreamEventFlags::ITEM_IS_DIR, 9)]);
+        assert_eq!(events[1], root_changed(&root, 9));
+        assert_eq!(watcher.take_change(), Some(RootChange::Reattached));
+        assert!(watcher.is_attached());
+    }
+
+    #[test]
+    fn test_symlink_swap() {
+        let dir = TestDir::new("root_symlink");
+        fs::create_dir(dir.path().join("release_1")).unwrap();
+        fs::create_dir(dir.path().join("release_2")).unwrap();
+        let current = dir.path().join("current");
+        symlink(dir.path().join("release_1"), &current).unwrap();
+
+        let mut watcher = RootWatcher::new(&current).with_follow_root(true);
+
+        symlink(dir.path().join("release_2"), dir.path().join("next")).unwrap();
+        fs::rename(dir.path().join("next"), &current).unwrap();
+        assert_eq!(watcher.check(), Some(RootChange::Reattached));
+        assert_eq!(watcher.check(), None);
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::event::{FSEventStreamEventFlags, FsEvent};
+
+const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+// The counterparts of FSEventStreamCreateFlags::IGNORE_SELF and MARK_SELF.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SelfEvents {
+    Ignore,
+    Mark,
+}
+
+struct Expected {
+    writers: usize,
+    until: Option<Instant>,
+}
+
+type ExpectedWrites = Arc<Mutex<HashMap<PathBuf, Expected>>>;
+
+// Drops or marks with OWN_EVENT the events caused by this process. Events that carry a
+// pid (fanotify) are judged by it; for the others (inotify) the process announces the
+// paths it is about to write with `expect_write`, and events on them or below them count
+// as its own while the write is going on and for a grace period after it, since events
+// are delivered late.
+#[derive(Clone)]
+pub struct SelfEventFilter {
+    mode: SelfEvents,
+    pid: u32,
+    grace_period: Duration,
+    expected: ExpectedWrites,
+}
+
+impl SelfEventFilter {
+    pub fn new(mode: SelfEvents) -> Self {
+        SelfEventFilter {
+            mode,
+            pid: std::process::id(),
+            grace_period: DEFAULT_GRACE_PERIOD,
+            expected: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn with_grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = grace_period;
+        self
+    }
+
+    pub fn mode(&self) -> SelfEvents {
+        self.mode
+    }
+
+    pub fn expect_write<P>(&self, path: P) -> OwnWrite where P: Into<PathBuf> {
+        let path = path.into();
+        let mut expected = self.expected.lock().unwrap();
+        let entry = expected.entry(path.clone()).or_insert(Expected { writers: 0, until: None });
+        entry.writers += 1;
+        entry.until = None;
+
+        OwnWrite { expected: self.expected.clone(), path, grace_period: self.grace_period }
+    }
+
+    pub fn is_own(&self, event: &FsEvent) -> bool {
+        match event.pid {
+            Some(pid) => pid == self.pid,
+            None => self.is_expected(&event.path, Instant::now()),
+        }
+    }
+
+    pub fn process(&self, events: Vec<FsEvent>) -> Vec<FsEvent> {
+        let now = Instant::now();
+        self.expected.lock().unwrap().retain(|_, expected| expected.until.is_none_or(|until| until > now));
+
+        events
+            .into_iter()
+            .filter_map(|mut event| {
+                if !self.is_own(&event) {
+                    return Some(event);
+                }
+
+                match self.mode {
+                    SelfEvents::Ignore => None,
+                    SelfEvents::Mark => {
+                        event.flags |= FSEventStreamEventFlags::OWN_EVENT;
+                        Some(event)
+                    }
+                }
+            })
+            .collect()
+    }
+
+    fn is_expected(&self, path: &Path, now: Instant) -> bool {
+        let expected = self.expected.lock().unwrap();
+        path.ancestors().any(|ancestor| match expected.get(ancestor) {
+            Some(expected) => expected.until.is_none_or(|until| until > now),
+            None => false,
+        })
+    }
+}
+
+// Keeps events on the path attributed to this process until it is dropped, and for the
+// grace period after that.
+pub struct OwnWrite {
+    expected: ExpectedWrites,
+    path: PathBuf,
+    grace_period: Duration,
+}
+
+impl Drop for OwnWrite {
+    fn drop(&mut self) {
+        let mut expected = self.expected.lock().unwrap();
+        if let Some(entry) = expected.get_mut(&self.path) {
+            entry.writers -= 1;
+            if entry.writers == 0 {
+                entry.until = Some(Instant::now() + self.grace_period);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn modified(path: &str) -> FsEvent {
+        FsEvent::new(path, FSEventStreamEventFlags::ITEM_MODIFIED | FSEventStreamEventFlags::ITEM_IS_FILE, 1)
+    }
+
+    #[test]
+    fn test_events_with_pid() {
+        let filter = SelfEventFilter::new(SelfEvents::Ignore);
+        let own = modified("/w/a").with_pid(std::process::id());
+        let other = modified("/w/b").with_pid(1);
+
+        assert_eq!(filter.process(vec![own, other.clone()]), vec![other]);
+    }
+
+    #[test]
+    fn test_expected_writes_are_marked() {
+        let filter = SelfEventFilter::new(SelfEvents::Mark).with_grace_period(Duration::from_millis(50));
+
+        let write = filter.expect_write("/w/dir");
+        let events = filter.process(vec![modified("/w/dir/file"), modified("/w/other")]);
+        assert!(events[0].flags.contains(FSEventStreamEventFlags::OWN_EVENT));
+        assert!(!events[1].flags.contains(FSEventStreamEventFlags::OWN_EVENT));
+
+        // Late events still count as our own for the grace period.
+        drop(write);
+        assert!(filter.is_own(&modified("/w/dir/file")));
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(!filter.process(vec![modified("/w/dir/file")])[0].flags.contains(FSEventStreamEventFlags::OWN_EVENT));
+    }
+
+    #[test]
+    fn test_pid_wins_over_expected_writes() {
+        let filter = SelfEventFilter::new(SelfEvents::Ignore);
+        let _write = filter.expect_write("/w/a");
+        let other = modified("/w/a").with_pid(1);
+        assert_eq!(filter.process(vec![other.clone()]), vec![other]);
+    }
+}
@@ -0,0 +1,523 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::ops::Bound;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use crate::event::{FSEventStreamEventFlags, FSEventStreamEventId, FsEvent};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+    Other,
+}
+
+impl EntryKind {
+    pub fn flags(&self) -> FSEventStreamEventFlags {
+        match self {
+            EntryKind::File => FSEventStreamEventFlags::ITEM_IS_FILE,
+            EntryKind::Dir => FSEventStreamEventFlags::ITEM_IS_DIR,
+            EntryKind::Symlink => FSEventStreamEventFlags::ITEM_IS_SYMLINK,
+            EntryKind::Other => FSEventStreamEventFlags::NONE,
+        }
+    }
+
+    fn to_char(self) -> char {
+        match self {
+            EntryKind::File => 'f',
+            EntryKind::Dir => 'd',
+            EntryKind::Symlink => 'l',
+            EntryKind::Other => 'o',
+        }
+    }
+
+    fn from_char(value: &str) -> Option<Self> {
+        match value {
+            "f" => Some(EntryKind::File),
+            "d" => Some(EntryKind::Dir),
+            "l" => Some(EntryKind::Symlink),
+            "o" => Some(EntryKind::Other),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub kind: EntryKind,
+    pub device: u64,
+    pub inode: u64,
+    pub size: u64,
+    pub mtime: (i64, i64),
+    pub ctime: (i64, i64),
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl Entry {
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        let file_type = metadata.file_type();
+        let kind = if file_type.is_symlink() {
+            EntryKind::Symlink
+        } else if file_type.is_dir() {
+            EntryKind::Dir
+        } else if file_type.is_file() {
+            EntryKind::File
+        } else {
+            EntryKind::Other
+        };
+
+        Entry {
+            kind,
+            device: metadata.dev(),
+            inode: metadata.ino(),
+            size: metadata.size(),
+            mtime: (metadata.mtime(), metadata.mtime_nsec()),
+            ctime: (metadata.ctime(), metadata.ctime_nsec()),
+            mode: metadata.mode(),
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+        }
+    }
+
+    fn is_same_item(&self, other: &Entry) -> bool {
+        self.kind == other.kind && self.device == other.device && self.inode == other.inode
+    }
+
+    // Inode numbers are reused quickly, so a file only counts as moved when its content
+    // stamps are unchanged too.
+    fn is_moved_item(&self, other: &Entry) -> bool {
+        self.is_same_item(other) && (self.kind == EntryKind::Dir || (self.size == other.size && self.mtime == other.mtime))
+    }
+
+    // The flags describing how `self` turned into `new` when both are the same item.
+    fn changes(&self, new: &Entry) -> FSEventStreamEventFlags {
+        let mut flags = FSEventStreamEventFlags::NONE;
+        let is_dir = self.kind == EntryKind::Dir;
+
+        // A directory's own size and times follow its children, which get their own events.
+        if !is_dir && (new.size != self.size || new.mtime != self.mtime) {
+            flags |= FSEventStreamEventFlags::ITEM_MODIFIED;
+        }
+
+        if new.uid != self.uid || new.gid != self.gid {
+            flags |= FSEventStreamEventFlags::ITEM_CHANGE_OWNER;
+        }
+
+        // Any change bumps ctime, so it only means "metadata" when nothing else explains it.
+        if new.mode != self.mode || (!is_dir && flags.is_empty() && new.ctime != self.ctime) {
+            flags |= FSEventStreamEventFlags::ITEM_INODE_META_MOD;
+        }
+
+        flags
+    }
+
+    fn write_line<W>(&self, path: &Path, writer: &mut W) -> io::Result<()> where W: Write {
+        writeln!(
+            writer,
+            "{} {} {} {} {} {} {} {} {:o} {} {} {}",
+            self.kind.to_char(),
+            self.device,
+            self.inode,
+            self.size,
+            self.mtime.0,
+            self.mtime.1,
+            self.ctime.0,
+            self.ctime.1,
+            self.mode,
+            self.uid,
+            self.gid,
+            escape_path(path),
+        )
+    }
+
+    fn parse_line(line: &str) -> Option<(PathBuf, Entry)> {
+        let fields: Vec<&str> = line.split(' ').collect();
+        if fields.len() != 12 {
+            return None;
+        }
+
+        let entry = Entry {
+            kind: EntryKind::from_char(fields[0])?,
+            device: fields[1].parse().ok()?,
+            inode: fields[2].parse().ok()?,
+            size: fields[3].parse().ok()?,
+            mtime: (fields[4].parse().ok()?, fields[5].parse().ok()?),
+            ctime: (fields[6].parse().ok()?, fields[7].parse().ok()?),
+            mode: u32::from_str_radix(fields[8], 8).ok()?,
+            uid: fields[9].parse().ok()?,
+            gid: fields[10].parse().ok()?,
+        };
+
+        Some((unescape_path(fields[11])?, entry))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    pub root: PathBuf,
+    pub entries: BTreeMap<PathBuf, Entry>,
+}
+
+impl Snapshot {
+    pub fn capture<P>(root: P) -> io::Result<Self> where P: Into<PathBuf> {
+        let root = root.into();
+        let mut entries = BTreeMap::new();
+        walk(&root, &mut entries)?;
+        Ok(Snapshot { root, entries })
+    }
+
+    pub fn save<P>(&self, path: P) -> io::Result<()> where P: AsRef<Path> {
+        let path = path.as_ref();
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+
+        let mut writer = BufWriter::new(File::create(&temporary)?);
+        self.write_to(&mut writer)?;
+        writer.into_inner().map_err(|error| error.into_error())?.sync_all()?;
+        fs::rename(&temporary, path)
+    }
+
+    pub fn load<P>(path: P) -> io::Result<Self> where P: AsRef<Path> {
+        Self::read_from(BufReader::new(File::open(path)?))
+    }
+
+    pub fn write_to<W>(&self, writer: &mut W) -> io::Result<()> where W: Write {
+        writeln!(writer, "{}", SNAPSHOT_HEADER)?;
+        writeln!(writer, "{}", escape_path(&self.root))?;
+        for (path, entry) in &self.entries {
+            entry.write_line(path, writer)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R>(reader: R) -> io::Result<Self> where R: BufRead {
+        let mut lines = reader.lines();
+
+        if lines.next().transpose()?.as_deref() != Some(SNAPSHOT_HEADER) {
+            return Err(invalid_data("missing snapshot header"));
+        }
+
+        let root = lines
+            .next()
+            .transpose()?
+            .and_then(|line| unescape_path(&line))
+            .ok_or_else(|| invalid_data("missing snapshot root"))?;
+
+        let mut entries = BTreeMap::new();
+        for line in lines {
+            let line = line?;
+            let (path, entry) = Entry::parse_line(&line)
+                .ok_or_else(|| invalid_data(&format!("invalid snapshot entry: {}", line)))?;
+            entries.insert(path, entry);
+        }
+
+        Ok(Snapshot { root, entries })
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&Entry> {
+        self.entries.get(path)
+    }
+
+    pub fn subtree<'a>(&'a self, path: &'a Path) -> impl Iterator<Item = (&'a PathBuf, &'a Entry)> + 'a {
+        self.entries
+            .range::<Path, _>((Bound::Included(path), Bound::Unbounded))
+            .take_while(move |(entry_path, _)| entry_path.starts_with(path))
+    }
+
+    // Re-walks `path` and everything below it, stores the result and returns the
+    // events that turn the previous state of that subtree into the current one.
+    pub fn rescan(&mut self, path: &Path, id: FSEventStreamEventId) -> Vec<FsEvent> {
+        let mut current = BTreeMap::new();
+        let _ = walk(path, &mut current);
+
+        let previous: BTreeMap<PathBuf, Entry> = self.subtree(path)
+            .map(|(entry_path, entry)| (entry_path.clone(), entry.clone()))
+            .collect();
+
+        let events = diff_entries(&previous, &current, id);
+
+        for entry_path in previous.keys() {
+            self.entries.remove(entry_path);
+        }
+        self.entries.extend(current);
+
+        events
+    }
+
+    // Brings a single path up to date without producing events, for paths the watcher
+    // already reported itself. Newly appearing directories are walked in full.
+    pub fn refresh(&mut self, path: &Path) {
+        match fs::symlink_metadata(path) {
+            Ok(metadata) => {
+                let entry = Entry::from_metadata(&metadata);
+                let is_new_dir = entry.kind == EntryKind::Dir && !self.entries.contains_key(path);
+                self.entries.insert(path.to_path_buf(), entry);
+                if is_new_dir {
+                    let _ = walk(path, &mut self.entries);
+                }
+            }
+            Err(_) => {
+                let removed: Vec<PathBuf> = self.subtree(path).map(|(entry_path, _)| entry_path.clone()).collect();
+                for entry_path in removed {
+                    self.entries.remove(&entry_path);
+                }
+            }
+        }
+    }
+}
+
+fn walk(path: &Path, entries: &mut BTreeMap<PathBuf, Entry>) -> io::Result<()> {
+    visit_tree(path, &mut |path, metadata| {
+        entries.insert(path.to_path_buf(), Entry::from_metadata(metadata));
+    })
+}
+
+pub(crate) fn visit_tree<F>(path: &Path, visit: &mut F) -> io::Result<()>
+    where F: FnMut(&Path, &Metadata)
+{
+    let metadata = fs::symlink_metadata(path)?;
+    visit(path, &metadata);
+
+    if !metadata.is_dir() {
+        return Ok(());
+    }
+
+    // Entries that disappear or cannot be read while walking are simply left out.
+    let children = match fs::read_dir(path) {
+        Ok(children) => children,
+        Err(_) => return Ok(()),
+    };
+
+    for child in children.flatten() {
+        let _ = visit_tree(&child.path(), visit);
+    }
+
+    Ok(())
+}
+
+// Yields the events that turn `old` into `new`, for example the state a service saved at
+// shutdown and the one it captured at startup.
+pub fn diff(old: &Snapshot, new: &Snapshot) -> Vec<FsEvent> {
+    diff_entries(&old.entries, &new.entries, 0)
+}
+
+pub(crate) fn diff_entries(previous: &BTreeMap<PathBuf, Entry>, current: &BTreeMap<PathBuf, Entry>, id: FSEventStreamEventId) -> Vec<FsEvent> {
+    let mut events = Vec::new();
+    let mut removed: Vec<(&PathBuf, &Entry)> = Vec::new();
+    let mut created: Vec<(&PathBuf, &Entry)> = Vec::new();
+
+    for (path, old) in previous {
+        match current.get(path) {
+            None => removed.push((path, old)),
+            Some(new) if !old.is_same_item(new) => {
+                removed.push((path, old));
+                created.push((path, new));
+            }
+            Some(new) => {
+                let changes = old.changes(new);
+                if !changes.is_empty() {
+                    events.push(FsEvent::new(path, changes | new.kind.flags(), id));
+                }
+            }
+        }
+    }
+
+    for (path, new) in current {
+        if !previous.contains_key(path) {
+            created.push((path, new));
+        }
+    }
+
+    // An item that vanished from one path and appeared at another was renamed; FSEvents
+    // reports both paths with ITEM_RENAMED.
+    let mut removed_by_inode: HashMap<(u64, u64), usize> = HashMap::new();
+    for (index, (_, old)) in removed.iter().enumerate() {
+        removed_by_inode.insert((old.device, old.inode), index);
+    }
+
+    let mut renamed_from = vec![false; removed.len()];
+    let mut renames = Vec::new();
+    created.retain(|(path, new)| {
+        match removed_by_inode.get(&(new.device, new.inode)) {
+            Some(&index) if !renamed_from[index] && removed[index].1.is_moved_item(new) && !current.contains_key(removed[index].0) => {
+                renamed_from[index] = true;
+                renames.push((removed[index].0, removed[index].1, *path, *new));
+                false
+            }
+            _ => true,
+        }
+    });
+
+    for (index, (path, old)) in removed.iter().enumerate() {
+        if !renamed_from[index] {
+            events.push(FsEvent::new(*path, FSEventStreamEventFlags::ITEM_REMOVED | old.kind.flags(), id));
+        }
+    }
+
+    // Ancestors sort first, so a moved directory is reported before its contents, which
+    // moved along with it and are not renames of their own.
+    renames.sort_by(|a, b| a.0.cmp(b.0));
+    let mut reported: Vec<(&PathBuf, &PathBuf)> = Vec::new();
+    for (old_path, old, new_path, new) in renames {
+        // The rename itself bumps ctime; only report metadata changes that happened on top.
+        let changes = old.changes(&Entry { ctime: old.ctime, ..new.clone() });
+
+        let moved_along = reported.iter().any(|(old_dir, new_dir)| {
+            match (old_path.strip_prefix(old_dir), new_path.strip_prefix(new_dir)) {
+                (Ok(old_relative), Ok(new_relative)) => old_relative == new_relative,
+                _ => false,
+            }
+        });
+        if moved_along {
+            if !changes.is_empty() {
+                events.push(FsEvent::new(new_path, changes | new.kind.flags(), id));
+            }
+            continue;
+        }
+
+        events.push(FsEvent::new(old_path, FSEventStreamEventFlags::ITEM_RENAMED | old.kind.flags(), id));
+        events.push(FsEvent::new(new_path, FSEventStreamEventFlags::ITEM_RENAMED | changes | new.kind.flags(), id));
+        if new.kind == EntryKind::Dir {
+            reported.push((old_path, new_path));
+        }
+    }
+
+    for (path, new) in created {
+        events.push(FsEvent::new(path, FSEventStreamEventFlags::ITEM_CREATED | new.kind.flags(), id));
+    }
+
+    events
+}
+
+const SNAPSHOT_HEADER: &str = "abstr-snapshot 1";
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// Paths are stored as bytes with everything outside printable ASCII, plus space and '%',
+// percent-encoded, so any path round-trips and each entry stays on one line.
+fn escape_path(path: &Path) -> String {
+    let mut escaped = String::new();
+    for &byte in path.as_os_str().as_bytes() {
+        if byte.is_ascii_graphic() && byte != b'%' {
+            escaped.push(byte as char);
+        } else {
+            escaped.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    escaped
+}
+
+fn unescape_path(escaped: &str) -> Option<PathBuf> {
+    let mut bytes = Vec::with_capacity(escaped.len());
+    let mut input = escaped.bytes();
+    while let Some(byte) = input.next() {
+        if byte == b'%' {
+            let high = (input.next()? as char).to_digit(16)?;
+            let low = (input.next()? as char).to_digit(16)?;
+            bytes.push((high * 16 + low) as u8);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    Some(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use crate::utils::TestDir;
+
+    fn flags_for(events: &[FsEvent], path: &Path) -> Vec<FSEventStreamEventFlags> {
+        events.iter().filter(|event| event.path == path).map(|event| event.flags).collect()
+    }
+
+    #[test]
+    fn test_diff_detects_rename() {
+        let dir = TestDir::new("snapshot_rename");
+        fs::write(dir.path().join("before"), "a").unwrap();
+        let old = Snapshot::capture(dir.path()).unwrap();
+
+        fs::rename(dir.path().join("before"), dir.path().join("after")).unwrap();
+        let new = Snapshot::capture(dir.path()).unwrap();
+
+        let events = diff(&old, &new);
+        assert_eq!(events.len(), 2);
+        assert_eq!(flags_for(&events, &dir.path().join("before")), vec![FSEventStreamEventFlags::ITEM_RENAMED | FSEventStreamEventFlags::ITEM_IS_FILE]);
+        assert_eq!(flags_for(&events, &dir.path().join("after")), vec![FSEventStreamEventFlags::ITEM_RENAMED | FSEventStreamEventFlags::ITEM_IS_FILE]);
+    }
+
+    #[test]
+    fn test_diff_reports_directory_rename_once() {
+        let dir = TestDir::new("snapshot_dir_rename");
+        fs::create_dir_all(dir.path().join("before/sub")).unwrap();
+        fs::write(dir.path().join("before/sub/file"), "a").unwrap();
+        let old = Snapshot::capture(dir.path()).unwrap();
+
+        fs::rename(dir.path().join("before"), dir.path().join("after")).unwrap();
+        fs::rename(dir.path().join("after/sub/file"), dir.path().join("after/sub/moved")).unwrap();
+        let new = Snapshot::capture(dir.path()).unwrap();
+
+        let events = diff(&old, &new);
+        let renamed = FSEventStreamEventFlags::ITEM_RENAMED;
+        assert_eq!(events.len(), 4);
+        assert_eq!(flags_for(&events, &dir.path().join("before")), vec![renamed | FSEventStreamEventFlags::ITEM_IS_DIR]);
+        assert_eq!(flags_for(&events, &dir.path().join("after")), vec![renamed | FSEventStreamEventFlags::ITEM_IS_DIR]);
+        // Moved on its own inside the moved directory.
+        assert_eq!(flags_for(&events, &dir.path().join("before/sub/file")), vec![renamed | FSEventStreamEventFlags::ITEM_IS_FILE]);
+        assert_eq!(flags_for(&events, &dir.path().join("after/sub/moved")), vec![renamed | FSEventStreamEventFlags::ITEM_IS_FILE]);
+    }
+
+    #[test]
+    fn test_diff_detects_permission_change() {
+        let dir = TestDir::new("snapshot_mode");
+        let file = dir.path().join("file");
+        fs::write(&file, "a").unwrap();
+        let old = Snapshot::capture(dir.path()).unwrap();
+
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o600)).unwrap();
+        let new = Snapshot::capture(dir.path()).unwrap();
+
+        assert_eq!(flags_for(&diff(&old, &new), &file), vec![FSEventStreamEventFlags::ITEM_INODE_META_MOD | FSEventStreamEventFlags::ITEM_IS_FILE]);
+    }
+
+    #[test]
+    fn test_diff_detects_owner_change() {
+        let mut old = BTreeMap::new();
+        let entry = Entry { kind: EntryKind::File, device: 1, inode: 2, size: 3, mtime: (4, 0), ctime: (4, 0), mode: 0o100644, uid: 501, gid: 20 };
+        old.insert(PathBuf::from("/root/file"), entry.clone());
+
+        let mut new = BTreeMap::new();
+        new.insert(PathBuf::from("/root/file"), Entry { uid: 0, ctime: (5, 0), ..entry });
+
+        assert_eq!(diff_entries(&old, &new, 3), vec![
+            FsEvent::new("/root/file", FSEventStreamEventFlags::ITEM_CHANGE_OWNER | FSEventStreamEventFlags::ITEM_IS_FILE, 3),
+        ]);
+    }
+
+    #[test]
+    fn test_save_and_load_round_trip() {
+        let dir = TestDir::new("snapshot_save");
+        fs::create_dir(dir.path().join("with space%")).unwrap();
+        fs::write(dir.path().join("with space%/line\nbreak"), "a").unwrap();
+        let snapshot = Snapshot::capture(dir.path()).unwrap();
+
+        let saved = dir.path().join("state");
+        snapshot.save(&saved).unwrap();
+        assert_eq!(Snapshot::load(&saved).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn test_load_rejects_invalid_data() {
+        let error = Snapshot::read_from("not a snapshot\n".as_bytes()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
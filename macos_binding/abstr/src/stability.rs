@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use crate::access::AccessKind;
+use crate::event::{rename_direction, FSEventStreamEventFlags, FsEvent};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Stable(pub PathBuf);
+
+struct Pending {
+    last_change: Instant,
+    observed: Option<(u64, SystemTime)>,
+}
+
+// Holds back files that are being written and reports them as `Stable` once nothing has
+// touched them for the quiet period and their size and mtime did not move during it.
+//
+// Time is passed in by the caller so the tracker can be driven from whatever timer the
+// watcher uses: call `poll` at `next_deadline`. A backend that knows when a writer closed
+// the file (IN_CLOSE_WRITE on Linux) reports it as an AccessKind::ClosedWrite event or
+// through `mark_closed_write` instead of waiting for the quiet period.
+pub struct StabilityTracker {
+    quiet_period: Duration,
+    pending: HashMap<PathBuf, Pending>,
+    closed: Vec<PathBuf>,
+    previous: Option<FsEvent>,
+}
+
+impl StabilityTracker {
+    pub fn new(quiet_period: Duration) -> Self {
+        StabilityTracker {
+            quiet_period,
+            pending: HashMap::new(),
+            closed: Vec::new(),
+            previous: None,
+        }
+    }
+
+    pub fn quiet_period(&self) -> Duration {
+        self.quiet_period
+    }
+
+    pub fn is_pending(&self, path: &Path) -> bool {
+        self.pending.contains_key(path)
+    }
+
+    pub fn record(&mut self, event: &FsEvent, now: Instant) {
+        if event.access == Some(AccessKind::ClosedWrite) {
+            self.mark_closed_write(&event.path);
+            return;
+        }
+
+        let flags = event.flags;
+        let previous = self.previous.replace(event.clone());
+
+        if flags.intersects(FSEventStreamEventFlags::ITEM_IS_DIR | FSEventStreamEventFlags::ITEM_IS_SYMLINK) {
+            return;
+        }
+
+        if flags.contains(FSEventStreamEventFlags::ITEM_RENAMED) {
+            // A file renamed while still pending is being written under its new name.
+            if let Some((from, to)) = previous.as_ref().and_then(|previous| rename_direction(previous, event)) {
+                if self.pending.remove(from).is_some() {
+                    self.touch(to, now);
+                }
+            }
+        }
+
+        if flags.intersects(FSEventStreamEventFlags::ITEM_CREATED | FSEventStreamEventFlags::ITEM_MODIFIED) {
+            self.touch(&event.path, now);
+        }
+
+        if flags.contains(FSEventStreamEventFlags::ITEM_REMOVED) && fs::symlink_metadata(&event.path).is_err() {
+            self.pending.remove(&event.path);
+            self.closed.retain(|closed| closed != &event.path);
+        }
+    }
+
+    // The last writer closed the file; it is reported on the next poll without waiting.
+    // Paths without modification activity have nothing to settle and are ignored.
+    pub fn mark_closed_write(&mut self, path: &Path) {
+        if self.pending.remove(path).is_none() {
+            return;
+        }
+        if !self.closed.iter().any(|closed| closed == path) {
+            self.closed.push(path.to_path_buf());
+        }
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        if !self.closed.is_empty() {
+            return Some(Instant::now());
+        }
+
+        self.pending.values().map(|pending| pending.last_change + self.quiet_period).min()
+    }
+
+    pub fn poll(&mut self, now: Instant) -> Vec<Stable> {
+        let mut stable: Vec<Stable> = self.closed.drain(..).map(Stable).collect();
+
+        let due: Vec<PathBuf> = self.pending
+            .iter()
+            .filter(|(_, pending)| pending.last_change + self.quiet_period <= now)
+            .map(|(path, _)| path.clone())
+            .collect();
+
+        for path in due {
+            let current = observe(&path);
+            if current.is_none() {
+                self.pending.remove(&path);
+                continue;
+            }
+
+            let pending = self.pending.get_mut(&path).unwrap();
+            if pending.observed == current {
+                self.pending.remove(&path);
+                stable.push(Stable(path));
+            } else {
+                // Written to without an event reaching us (yet): start over.
+                pending.observed = current;
+                pending.last_change = now;
+            }
+        }
+
+        stable.sort_by(|a, b| a.0.cmp(&b.0));
+        stable
+    }
+
+    fn touch(&mut self, path: &Path, now: Instant) {
+        let observed = observe(path);
+        self.closed.retain(|closed| closed != path);
+        self.pending.insert(path.to_path_buf(), Pending { last_change: now, observed });
+    }
+}
+
+fn observe(path: &Path) -> Option<(u64, SystemTime)> {
+    let metadata = fs::metadata(path).ok()?;
+    if !metadata.is_file() {
+        return None;
+    }
+
+    Some((metadata.len(), metadata.modified().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TestDir;
+
+    const QUIET: Duration = Duration::from_millis(100);
+
+    fn modified(path: &Path, id: u64) -> FsEvent {
+        FsEvent::new(path, FSEventStreamEventFlags::ITEM_MODIFIED | FSEventStreamEventFlags::ITEM_IS_FILE, id)
+    }
+
+    #[test]
+    fn test_stable_after_quiet_period() {
+        let dir = TestDir::new("stability_quiet");
+        let file = dir.path().join("upload");
+        fs::write(&file, "partial").unwrap();
+
+        let start = Instant::now();
+        let mut tracker = StabilityTracker::new(QUIET);
+        tracker.record(&modified(&file, 1), start);
+
+        assert_eq!(tracker.next_deadline(), Some(start + QUIET));
+        assert!(tracker.poll(start + QUIET / 2).is_empty());
+
+        // Further activity pushes the deadline back.
+        tracker.record(&modified(&file, 2), start + QUIET / 2);
+        assert!(tracker.poll(start + QUIET).is_empty());
+
+        assert_eq!(tracker.poll(start + QUIET * 2), vec![Stable(file.clone())]);
+        assert!(!tracker.is_pending(&file));
+        assert_eq!(tracker.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_unreported_write_restarts_quiet_period() {
+        let dir = TestDir::new("stability_unreported");
+        let file = dir.path().join("upload");
+        fs::write(&file, "a").unwrap();
+
+        let start = Instant::now();
+        let mut tracker = StabilityTracker::new(QUIET);
+        tracker.record(&modified(&file, 1), start);
+
+        fs::write(&file, "grown").unwrap();
+        assert!(tracker.poll(start + QUIET).is_empty());
+        assert!(tracker.is_pending(&file));
+        assert_eq!(tracker.poll(start + QUIET * 2), vec![Stable(file)]);
+    }
+
+    #[test]
+    fn test_closed_write_and_removal() {
+        let dir = TestDir::new("stability_closed");
+        let closed = dir.path().join("closed");
+        let removed = dir.path().join("removed");
+        fs::write(&closed, "a").unwrap();
+        fs::write(&removed, "a").unwrap();
+
+        let start = Instant::now();
+        let mut tracker = StabilityTracker::new(QUIET);
+        tracker.record(&modified(&closed, 1), start);
+        tracker.record(&modified(&removed, 2), start);
+
+        tracker.mark_closed_write(&closed);
+        fs::remove_file(&removed).unwrap();
+        tracker.record(&FsEvent::new(&removed, FSEventStreamEventFlags::ITEM_REMOVED | FSEventStreamEventFlags::ITEM_IS_FILE, 3), start);
+
+        assert_eq!(tracker.poll(start), vec![Stable(closed)]);
+        assert!(tracker.poll(start + QUIET).is_empty());
+    }
+
+    #[test]
+    fn test_closed_write_of_untracked_path() {
+        let dir = TestDir::new("stability_untracked");
+        let file = dir.path().join("file");
+        fs::write(&file, "a").unwrap();
+
+        let start = Instant::now();
+        let mut tracker = StabilityTracker::new(QUIET);
+        tracker.mark_closed_write(&file);
+        tracker.record(&FsEvent::new(&file, FSEventStreamEventFlags::ITEM_IS_FILE, 1).with_access(AccessKind::ClosedWrite), start);
+
+        assert!(tracker.poll(start).is_empty());
+        assert_eq!(tracker.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_closed_write_access_event() {
+        let dir = TestDir::new("stability_access");
+        let file = dir.path().join("file");
+        fs::write(&file, "a").unwrap();
+
+        let start = Instant::now();
+        let mut tracker = StabilityTracker::new(QUIET);
+        tracker.record(&modified(&file, 1), start);
+
+        // Closing without writing says nothing about the write being done.
+        tracker.record(&FsEvent::new(&file, FSEventStreamEventFlags::ITEM_IS_FILE, 2).with_access(AccessKind::ClosedNoWrite), start);
+        assert!(tracker.poll(start).is_empty());
+
+        tracker.record(&FsEvent::new(&file, FSEventStreamEventFlags::ITEM_IS_FILE, 3).with_access(AccessKind::ClosedWrite), start);
+        assert_eq!(tracker.poll(start), vec![Stable(file)]);
+    }
+
+    #[test]
+    fn test_rename_keeps_tracking() {
+        let dir = TestDir::new("stability_rename");
+        let partial = dir.path().join("upload.part");
+        let done = dir.path().join("upload");
+        fs::write(&partial, "a").unwrap();
+
+        let start = Instant::now();
+        let mut tracker = StabilityTracker::new(QUIET);
+        tracker.record(&modified(&partial, 1), start);
+
+        fs::rename(&partial, &done).unwrap();
+        tracker.record(&FsEvent::new(&partial, FSEventStreamEventFlags::ITEM_RENAMED | FSEventStreamEventFlags::ITEM_IS_FILE, 2), start);
+        tracker.record(&FsEvent::new(&done, FSEventStreamEventFlags::ITEM_RENAMED | FSEventStreamEventFlags::ITEM_IS_FILE, 3), start);
+
+        assert_eq!(tracker.poll(start + QUIET), vec![Stable(done)]);
+    }
+}
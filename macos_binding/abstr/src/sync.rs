@@ -0,0 +1,263 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use crate::event::{FSEventStreamEventFlags, FsEvent};
+
+const COOKIE_PREFIX: &str = ".fsw-sync-cookie-";
+
+static NEXT_COOKIE: AtomicU64 = AtomicU64::new(0);
+
+// A barrier for backends without FSEventStreamFlushSync: `sync` writes a cookie file into
+// each root and returns once the watcher delivered the events for all of them. Backends
+// report events in order, so everything that happened before is delivered by then. The
+// events of cookie files never reach the consumer.
+//
+// A file root cannot hold a cookie, so it goes next to the file. Backends watch
+// `watch_paths` instead of the roots and narrow what they deliver with `within`.
+#[derive(Clone, Debug, Default)]
+pub struct SyncBarrier {
+    state: Arc<(Mutex<HashSet<String>>, Condvar)>,
+}
+
+impl SyncBarrier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_cookie(path: &Path) -> bool {
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(COOKIE_PREFIX))
+    }
+
+    // The directories a backend has to watch for `roots`: each root itself, or the
+    // directory of a file root. Duplicates are left out.
+    pub fn watch_paths<P>(roots: &[P]) -> Vec<PathBuf> where P: AsRef<Path> {
+        let mut paths: Vec<PathBuf> = Vec::new();
+        for root in roots {
+            let path = Self::cookie_directory(root.as_ref()).to_path_buf();
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+        paths
+    }
+
+    // Drops the events `watch_paths` added for the siblings of file roots. The end of the
+    // history and rescans of a directory above a root still concern the roots.
+    pub fn within<P>(roots: &[P], events: Vec<FsEvent>) -> Vec<FsEvent> where P: AsRef<Path> {
+        events
+            .into_iter()
+            .filter(|event| {
+                event.flags.contains(FSEventStreamEventFlags::HISTORY_DONE)
+                    || roots.iter().any(|root| {
+                        let root = root.as_ref();
+                        event.path.starts_with(root)
+                            || (event.flags.contains(FSEventStreamEventFlags::MUST_SCAN_SUB_DIRS) && root.starts_with(&event.path))
+                    })
+            })
+            .collect()
+    }
+
+    fn cookie_directory(root: &Path) -> &Path {
+        if root.is_dir() {
+            root
+        } else {
+            root.parent().unwrap_or(root)
+        }
+    }
+
+    // Must not be called from the thread that delivers the events. Fails with TimedOut
+    // when the watcher did not report every cookie in time.
+    pub fn sync<P>(&self, roots: &[P], timeout: Duration) -> io::Result<()> where P: AsRef<Path> {
+        let (pending, condvar) = &*self.state;
+        let deadline = Instant::now() + timeout;
+
+        let mut cookies: Vec<(PathBuf, String)> = Vec::new();
+        for root in roots {
+            let name = format!("{}{}-{}", COOKIE_PREFIX, std::process::id(), NEXT_COOKIE.fetch_add(1, Ordering::SeqCst));
+            // Registered first: the event may be delivered before the write returns.
+            pending.lock().unwrap().insert(name.clone());
+            let path = Self::cookie_directory(root.as_ref()).join(&name);
+            cookies.push((path.clone(), name.clone()));
+
+            if let Err(error) = fs::write(&path, []) {
+                self.abandon(&cookies);
+                return Err(error);
+            }
+        }
+
+        let guard = pending.lock().unwrap();
+        let (guard, _) = condvar
+            .wait_timeout_while(guard, deadline.saturating_duration_since(Instant::now()), |pending| {
+                cookies.iter().any(|(_, name)| pending.contains(name))
+            })
+            .unwrap();
+        let observed = cookies.iter().all(|(_, name)| !guard.contains(name));
+        drop(guard);
+
+        self.abandon(&cookies);
+        if observed {
+            Ok(())
+        } else {
+            Err(io::Error::new(io::ErrorKind::TimedOut, "watcher did not report the sync cookies in time"))
+        }
+    }
+
+    // Waiters are only released by `deliver`, after the events before their cookie
+    // reached the consumer.
+    fn split(&self, events: Vec<FsEvent>) -> (Vec<FsEvent>, Vec<String>) {
+        let mut observed = Vec::new();
+        let events = events
+            .into_iter()
+            .filter(|event| {
+                if !Self::is_cookie(&event.path) {
+                    return true;
+                }
+                if let Some(name) = event.path.file_name().and_then(|name| name.to_str()) {
+                    observed.push(name.to_string());
+                }
+                false
+            })
+            .collect();
+        (events, observed)
+    }
+
+    // Passes `events` without cookies to `callback` and then releases the `sync` calls
+    // whose cookies were among them.
+    pub fn deliver<F>(&self, events: Vec<FsEvent>, callback: F) where F: FnOnce(Vec<FsEvent>) {
+        let (events, observed) = self.split(events);
+        if !events.is_empty() {
+            callback(events);
+        }
+
+        if !observed.is_empty() {
+            let (pending, condvar) = &*self.state;
+            let mut pending = pending.lock().unwrap();
+            for name in observed {
+                pending.remove(&name);
+            }
+            condvar.notify_all();
+        }
+    }
+
+    fn abandon(&self, cookies: &[(PathBuf, String)]) {
+        let mut pending = self.state.0.lock().unwrap();
+        for (path, name) in cookies {
+            pending.remove(name);
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+    use crate::utils::TestDir;
+
+    fn created(path: PathBuf) -> FsEvent {
+        FsEvent::new(path, FSEventStreamEventFlags::ITEM_CREATED | FSEventStreamEventFlags::ITEM_IS_FILE, 1)
+    }
+
+    #[test]
+    fn test_sync_waits_for_cookies() {
+        let first = TestDir::new("sync_first");
+        let second = TestDir::new("sync_second");
+        let barrier = SyncBarrier::new();
+        let (sender, receiver) = mpsc::channel();
+
+        // Stands in for a backend: reports what appears in the roots, cookies included.
+        let watcher = barrier.clone();
+        let roots = vec![first.path().to_path_buf(), second.path().to_path_buf()];
+        let scanned = roots.clone();
+        let handle = thread::spawn(move || {
+            let mut seen = HashSet::new();
+            while seen.len() < 3 {
+                let mut events = Vec::new();
+                for root in &scanned {
+                    for entry in fs::read_dir(root).unwrap().flatten() {
+                        if seen.insert(entry.path()) {
+                            events.push(created(entry.path()));
+                        }
+                    }
+                }
+                watcher.deliver(events, |events| sender.send(events).unwrap());
+                thread::sleep(Duration::from_millis(5));
+            }
+        });
+
+        fs::write(first.path().join("data"), b"x").unwrap();
+        barrier.sync(&roots, Duration::from_secs(5)).unwrap();
+        handle.join().unwrap();
+
+        let delivered: Vec<FsEvent> = receiver.try_iter().flatten().collect();
+        assert_eq!(delivered, vec![created(first.path().join("data"))]);
+        assert_eq!(fs::read_dir(first.path()).unwrap().count(), 1);
+        assert_eq!(fs::read_dir(second.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let dir = TestDir::new("sync_timeout");
+        let barrier = SyncBarrier::new();
+
+        let error = barrier.sync(&[dir.path()], Duration::from_millis(20)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_file_root() {
+        let dir = TestDir::new("sync_file");
+        let file = dir.path().join("file");
+        fs::write(&file, "a").unwrap();
+        fs::write(dir.path().join("sibling"), "a").unwrap();
+        let barrier = SyncBarrier::new();
+        let (sender, receiver) = mpsc::channel();
+
+        let roots = vec![file.clone()];
+        assert_eq!(SyncBarrier::watch_paths(&roots), vec![dir.path().to_path_buf()]);
+
+        // Watches the directory of the file, as a backend would.
+        let watcher = barrier.clone();
+        let watched = SyncBarrier::watch_paths(&roots);
+        let narrowed = roots.clone();
+        let handle = thread::spawn(move || {
+            let mut seen = HashSet::new();
+            let mut cookie_seen = false;
+            while !cookie_seen {
+                let mut events = Vec::new();
+                for entry in fs::read_dir(&watched[0]).unwrap().flatten() {
+                    if seen.insert(entry.path()) {
+                        cookie_seen |= SyncBarrier::is_cookie(&entry.path());
+                        events.push(created(entry.path()));
+                    }
+                }
+                watcher.deliver(events, |events| {
+                    let events = SyncBarrier::within(&narrowed, events);
+                    if !events.is_empty() {
+                        sender.send(events).unwrap();
+                    }
+                });
+                thread::sleep(Duration::from_millis(5));
+            }
+        });
+
+        barrier.sync(&roots, Duration::from_secs(5)).unwrap();
+        handle.join().unwrap();
+
+        let delivered: Vec<FsEvent> = receiver.try_iter().flatten().collect();
+        assert_eq!(delivered, vec![created(file.clone())]);
+
+        let rescan = FsEvent::new(dir.path(), FSEventStreamEventFlags::MUST_SCAN_SUB_DIRS, 2);
+        let sibling = created(dir.path().join("sibling"));
+        assert_eq!(SyncBarrier::within(&roots, vec![rescan.clone(), sibling]), vec![rescan]);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+}
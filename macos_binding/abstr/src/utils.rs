@@ -0,0 +1,34 @@
+#[cfg(test)]
+pub(crate) use test_dir::TestDir;
+
+#[cfg(test)]
+mod test_dir {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    pub(crate) struct TestDir {
+        path: PathBuf,
+    }
+
+    impl TestDir {
+        pub(crate) fn new(name: &str) -> Self {
+            let unique = format!("abstr_{}_{}_{}", name, std::process::id(), COUNTER.fetch_add(1, Ordering::SeqCst));
+            let path = std::env::temp_dir().join(unique);
+            fs::create_dir_all(&path).unwrap();
+            TestDir { path: path.canonicalize().unwrap() }
+        }
+
+        pub(crate) fn path(&self) -> &Path {
+            &self.path
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::BTreeSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::snapshot::visit_tree;
+
+// The directories an inotify backend has to watch for a set of roots. inotify watches a
+// single directory at a time, so roots are added and removed by adding and removing the
+// watches below them, without touching the ones other roots still need; unlike an
+// FSEvents stream nothing is restarted and the other roots keep reporting throughout.
+#[derive(Clone, Debug, Default)]
+pub struct WatchSet {
+    roots: Vec<PathBuf>,
+    directories: BTreeSet<PathBuf>,
+}
+
+impl WatchSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    pub fn is_watched(&self, directory: &Path) -> bool {
+        self.directories.contains(directory)
+    }
+
+    // Returns the directories to start watching, none when the root already was, or was
+    // below another root. A file root is watched through itself.
+    pub fn add_root<P>(&mut self, root: P) -> io::Result<Vec<PathBuf>> where P: Into<PathBuf> {
+        let root = root.into();
+        if self.roots.contains(&root) {
+            return Ok(Vec::new());
+        }
+
+        let mut added = Vec::new();
+        let mut is_root = true;
+        visit_tree(&root, &mut |path, metadata| {
+            if (is_root || metadata.is_dir()) && self.directories.insert(path.to_path_buf()) {
+                added.push(path.to_path_buf());
+            }
+            is_root = false;
+        })?;
+
+        self.roots.push(root);
+        Ok(added)
+    }
+
+    // Returns the directories to stop watching, leaving those still below another root.
+    pub fn remove_root(&mut self, root: &Path) -> Vec<PathBuf> {
+        let Some(index) = self.roots.iter().position(|watched| watched == root) else {
+            return Vec::new();
+        };
+        self.roots.remove(index);
+
+        let removed: Vec<PathBuf> = self
+            .directories
+            .iter()
+            .filter(|directory| directory.starts_with(root))
+            .filter(|directory| !self.roots.iter().any(|other| directory.starts_with(other)))
+            .cloned()
+            .collect();
+        for directory in &removed {
+            self.directories.remove(directory);
+        }
+        removed
+    }
+
+    // For a directory created below a root after it was added; returns whether it has to
+    // be watched.
+    pub fn add_directory(&mut self, directory: &Path) -> bool {
+        self.roots.iter().any(|root| directory.starts_with(root)) && self.directories.insert(directory.to_path_buf())
+    }
+
+    pub fn remove_directory(&mut self, directory: &Path) -> bool {
+        self.directories.remove(directory)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::utils::TestDir;
+
+    #[test]
+    fn test_roots_share_watches() {
+        let dir = TestDir::new("watch_set");
+        let outer = dir.path().join("outer");
+        let inner = outer.join("inner");
+        fs::create_dir_all(inner.join("deep")).unwrap();
+        fs::write(inner.join("file"), "a").unwrap();
+
+        let mut watches = WatchSet::new();
+        let mut added = watches.add_root(&inner).unwrap();
+        added.sort();
+        assert_eq!(added, vec![inner.clone(), inner.join("deep")]);
+
+        // Only what the inner root did not already watch.
+        assert_eq!(watches.add_root(&outer).unwrap(), vec![outer.clone()]);
+        assert!(watches.add_root(&outer).unwrap().is_empty());
+
+        assert_eq!(watches.remove_root(&outer), vec![outer.clone()]);
+        assert!(watches.is_watched(&inner.join("deep")));
+        assert!(!watches.add_directory(&dir.path().join("elsewhere")));
+        assert!(watches.add_directory(&inner.join("new")));
+
+        let mut removed = watches.remove_root(&inner);
+        removed.sort();
+        assert_eq!(removed, vec![inner.clone(), inner.join("deep"), inner.join("new")]);
+        assert!(watches.roots().is_empty());
+    }
+}
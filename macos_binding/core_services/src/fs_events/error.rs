@@ -0,0 +1,17 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExclusionError {
+    StreamStarted,
+}
+
+impl Display for ExclusionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExclusionError::StreamStarted => write!(f, "Cannot exclude paths on a started stream"),
+        }
+    }
+}
+
+impl Error for ExclusionError {}
@@ -67,4 +67,7 @@ extern "C" {
 
     #[link_name = "FSEventStreamGetDeviceBeingWatched"]
     pub fn stream_get_device_being_watched(streamRef: ConstFSEventStreamRef) -> dev_t;
+
+    #[link_name = "FSEventsGetCurrentEventId"]
+    pub fn get_current_event_id() -> FSEventStreamEventId;
 }
\ No newline at end of file
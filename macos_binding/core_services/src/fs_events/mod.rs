@@ -3,7 +3,8 @@ use std::{
     fmt::{Debug, Formatter},
     mem,
     os::macos::raw::dev_t,
-    sync::Arc,
+    path::Path,
+    sync::{Arc, RwLock},
     time::Duration,
 };
 
@@ -13,20 +14,30 @@ use core_foundation::{
     string::CFString,
 };
 
+use abstr::exclusion::ExclusionPlan;
 use dispatch::queue::Queue;
 
 use crate::fs_events::{
     context::{FileSystemEventStreamContext, RawFSEventStreamContext},
-    ffi::{FSEventStreamRef, stream_create, stream_flush_async, stream_flush_sync, stream_get_device_being_watched, stream_get_latest_event_id, stream_release, stream_retain, stream_set_dispatch_queue, stream_set_exclusion_paths, stream_show, stream_start, stream_stop},
+    error::ExclusionError,
+    ffi::{FSEventStreamRef, stream_create, stream_flush_async, stream_flush_sync, stream_get_device_being_watched, stream_get_latest_event_id, stream_invalidate, stream_release, stream_retain, stream_set_dispatch_queue, stream_set_exclusion_paths, stream_show, stream_start, stream_stop},
     r#enum::{FSEventStreamPointInTime, FSEventStreamCreateFlags, FSEventStreamEventFlags, FSEventStreamEventId},
 };
 
 pub mod ffi;
 pub mod context;
 pub mod r#enum;
+pub mod error;
+pub mod watcher;
 
 pub trait EventStreamCallback = Fn(Vec<*mut c_void>, isize, Vec<String>, Vec<FSEventStreamEventFlags>, Vec<FSEventStreamEventId>) -> ();
 
+// What info[0] points to: the user callback and the exclusions the kernel could not take.
+struct ExcludingCallback<F> {
+    callback: F,
+    exclusions: Arc<RwLock<ExclusionPlan>>,
+}
+
 extern "C" fn event_stream_callback<F>(_: *const FSEventStreamRef, info: *mut c_void, num_events: isize, event_paths: *mut *mut c_char, event_flags: *const FSEventStreamEventFlags, event_ids: *const FSEventStreamEventId)
     where F: EventStreamCallback
 {
@@ -70,10 +81,31 @@ extern "C" fn event_stream_callback<F>(_: *const FSEventStreamRef, info: *mut c_
             panic!("info must contain at least one element");
         }
 
-        let callback_fn = unsafe { &*(info[0] as *mut F) };
+        let excluding = unsafe { &*(info[0] as *mut ExcludingCallback<F>) };
         let additional_info = info[1..].to_vec();
 
-        callback_fn(additional_info, num_events, event_paths, event_flags, event_ids);
+        let exclusions = excluding.exclusions.read().unwrap();
+        if exclusions.user_space.is_empty() {
+            drop(exclusions);
+            (excluding.callback)(additional_info, num_events, event_paths, event_flags, event_ids);
+            return;
+        }
+
+        let mut kept_paths = Vec::with_capacity(event_paths.len());
+        let mut kept_flags = Vec::with_capacity(event_flags.len());
+        let mut kept_ids = Vec::with_capacity(event_ids.len());
+        for ((path, flags), id) in event_paths.into_iter().zip(event_flags).zip(event_ids) {
+            if !exclusions.is_excluded(Path::new(&path)) {
+                kept_paths.push(path);
+                kept_flags.push(flags);
+                kept_ids.push(id);
+            }
+        }
+        drop(exclusions);
+
+        if !kept_paths.is_empty() {
+            (excluding.callback)(additional_info, kept_paths.len() as isize, kept_paths, kept_flags, kept_ids);
+        }
     }
 }
 
@@ -81,6 +113,7 @@ pub struct FileSystemEventStream<'a> {
     stream_ref: FSEventStreamRef,
     is_started: bool,
     queue: Option<&'a Queue>,
+    exclusions: Arc<RwLock<ExclusionPlan>>,
 }
 
 impl<'a> FileSystemEventStream<'a> {
@@ -101,7 +134,9 @@ impl<'a> FileSystemEventStream<'a> {
 
         let cf_array: CFArray<CFString> = CFArray::from_CFTypes(&cf_strings);
 
-        let callback_ptr = Box::into_raw(Box::new(callback)) as *mut c_void;
+        let exclusions = Arc::new(RwLock::new(ExclusionPlan::default()));
+        let excluding = ExcludingCallback { callback, exclusions: exclusions.clone() };
+        let callback_ptr = Box::into_raw(Box::new(excluding)) as *mut c_void;
         let mut info_container: Vec<*mut c_void> = vec![callback_ptr];
 
         if let Some(info) = context.info.as_mut() {
@@ -129,7 +164,7 @@ impl<'a> FileSystemEventStream<'a> {
                 flags,
             )
         };
-        Self { stream_ref, is_started: false, queue: None }
+        Self { stream_ref, is_started: false, queue: None, exclusions }
     }
 
     pub fn set_dispatch_queue(&mut self, queue: &'a Queue) {
@@ -144,21 +179,34 @@ impl<'a> FileSystemEventStream<'a> {
         self.queue = Some(queue);
     }
 
-    pub fn exclude_paths(&self, paths_to_exclude: Vec<&str>) {
+    // The kernel takes at most MAX_KERNEL_EXCLUSIONS paths and rejects the whole list
+    // otherwise, so only the most effective ones are handed to it and the rest are
+    // filtered out before the callback runs. The returned plan tells which is which, and
+    // whether the kernel refused its part so that all of it is filtered in user space.
+    pub fn exclude_paths(&self, paths_to_exclude: Vec<&str>) -> Result<ExclusionPlan, ExclusionError> {
         if self.is_started {
-            panic!("Cannot exclude path on a started stream");
+            return Err(ExclusionError::StreamStarted);
         }
 
-        let cf_strings: Vec<CFString> = paths_to_exclude
+        let mut plan = ExclusionPlan::new(&paths_to_exclude);
+
+        let cf_strings: Vec<CFString> = plan.kernel
             .iter()
-            .map(|path| CFString::new(path))
+            .map(|path| CFString::new(&path.to_string_lossy()))
             .collect();
 
         let cf_array: CFArray<CFString> = CFArray::from_CFTypes(&cf_strings);
 
-        unsafe {
-            stream_set_exclusion_paths(self.stream_ref, cf_array.as_concrete_TypeRef());
+        let accepted = unsafe {
+            stream_set_exclusion_paths(self.stream_ref, cf_array.as_concrete_TypeRef())
+        };
+
+        if accepted == 0 {
+            plan = plan.user_space_only();
         }
+
+        *self.exclusions.write().unwrap() = plan.clone();
+        Ok(plan)
     }
 
     pub fn start(&mut self) {
@@ -180,6 +228,20 @@ impl<'a> FileSystemEventStream<'a> {
         self.is_started = false;
     }
 
+    // Unschedules a stopped stream from its queue, which FSEvents requires before the
+    // last release of a stream that was given one.
+    pub fn invalidate(&mut self) {
+        if self.is_started {
+            panic!("Cannot invalidate a started stream");
+        }
+
+        unsafe {
+            stream_invalidate(self.stream_ref);
+        }
+
+        self.queue = None;
+    }
+
     pub fn flush(&self) {
         if !self.is_started {
             panic!("Cannot flush a stopped stream");
@@ -232,6 +294,7 @@ impl<'a> Clone for FileSystemEventStream<'a> {
             stream_ref: self.stream_ref,
             is_started: self.is_started,
             queue: self.queue.clone(),
+            exclusions: self.exclusions.clone(),
         }
     }
 }
@@ -0,0 +1,268 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use abstr::{
+    event::FsEvent,
+    exclusion::ExclusionPlan,
+    handover::{Generation, StreamHandover},
+    rescan::Rescanner,
+    sync::SyncBarrier,
+};
+use dispatch::queue::Queue;
+
+use crate::fs_events::{
+    FileSystemEventStream,
+    context::FileSystemEventStreamContext,
+    error::ExclusionError,
+    ffi::get_current_event_id,
+    r#enum::{FSEventStreamCreateFlags, FSEventStreamPointInTime},
+};
+
+type Callback = dyn Fn(Vec<FsEvent>) + Send + Sync;
+
+// A stream whose paths can change. FSEvents streams cannot, so every change starts a new
+// stream from the latest event id of the current one and only stops the current one once
+// the new one runs; `StreamHandover` drops what both of them deliver.
+pub struct Watcher<'a> {
+    paths: Vec<String>,
+    excluded: Vec<String>,
+    latency: f64,
+    flags: FSEventStreamCreateFlags,
+    queue: &'a Queue,
+    callback: Arc<Callback>,
+    handover: Arc<Mutex<StreamHandover>>,
+    barrier: SyncBarrier,
+    rescanner: Arc<Mutex<Option<Rescanner>>>,
+    stream: Option<FileSystemEventStream<'a>>,
+    is_started: bool,
+}
+
+impl<'a> Watcher<'a> {
+    pub fn new<F>(
+        paths: &Vec<&str>,
+        since_when: FSEventStreamPointInTime,
+        latency: f64,
+        flags: FSEventStreamCreateFlags,
+        queue: &'a Queue,
+        callback: F,
+    ) -> Self
+        where F: 'static + Send + Sync + Fn(Vec<FsEvent>)
+    {
+        let mut watcher = Watcher {
+            paths: paths.iter().map(|path| path.to_string()).collect(),
+            excluded: Vec::new(),
+            latency,
+            flags,
+            queue,
+            callback: Arc::new(callback),
+            handover: Arc::new(Mutex::new(StreamHandover::new())),
+            barrier: SyncBarrier::new(),
+            rescanner: Arc::new(Mutex::new(None)),
+            stream: None,
+            is_started: false,
+        };
+
+        if !watcher.paths.is_empty() {
+            watcher.stream = Some(watcher.create_stream(since_when, 0));
+        }
+        watcher
+    }
+
+    pub fn paths(&self) -> &[String] {
+        &self.paths
+    }
+
+    pub fn stream(&self) -> Option<&FileSystemEventStream<'a>> {
+        self.stream.as_ref()
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.is_started
+    }
+
+    // Kept across restarts.
+    pub fn exclude_paths(&mut self, paths_to_exclude: Vec<&str>) -> Result<ExclusionPlan, ExclusionError> {
+        self.excluded = paths_to_exclude.iter().map(|path| path.to_string()).collect();
+        match self.stream.as_ref() {
+            Some(stream) => stream.exclude_paths(paths_to_exclude),
+            None => Ok(ExclusionPlan::new(&paths_to_exclude)),
+        }
+    }
+
+    pub fn start(&mut self) {
+        if let Some(stream) = self.stream.as_mut() {
+            stream.start();
+        }
+        self.is_started = true;
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(stream) = self.stream.as_mut() {
+            stream.stop();
+        }
+        self.is_started = false;
+    }
+
+    // From now on MUST_SCAN_SUB_DIRS and dropped events are replaced by the changes they
+    // stand for, found by comparing against a snapshot of every watched path.
+    pub fn enable_rescan(&mut self) -> io::Result<()> {
+        let roots: Vec<PathBuf> = self.paths.iter().map(|path| canonical(path)).collect();
+        *self.rescanner.lock().unwrap() = Some(Rescanner::new(&roots)?);
+        Ok(())
+    }
+
+    // Returns once everything that happened in the watched paths before the call was
+    // delivered, the same on every backend. Must not be called from the queue the
+    // callback runs on.
+    pub fn sync(&self, timeout: Duration) -> io::Result<()> {
+        match self.stream.as_ref() {
+            Some(stream) if stream.is_started && self.uses_cookies() => {
+                // Saves waiting out the latency for the cookies.
+                stream.flush_async();
+                self.barrier.sync(&self.paths, timeout)
+            }
+            // Only per-file events report the cookies; FlushSync is enough without them.
+            Some(stream) if stream.is_started => {
+                stream.flush();
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // File roots are then watched through their directories, so that the cookies
+    // written next to them are reported.
+    fn uses_cookies(&self) -> bool {
+        self.flags.contains(FSEventStreamCreateFlags::FILE_EVENTS)
+    }
+
+    // Returns false when the path was already watched.
+    pub fn add_path(&mut self, path: &str) -> bool {
+        if self.paths.iter().any(|watched| watched == path) {
+            return false;
+        }
+
+        self.paths.push(path.to_string());
+        if let Some(rescanner) = self.rescanner.lock().unwrap().as_mut() {
+            // Without a snapshot the path is not rescanned, which is no worse than before.
+            let _ = rescanner.add_root(canonical(path));
+        }
+        self.restart(Vec::new());
+        true
+    }
+
+    // Returns false when the path was not watched.
+    pub fn remove_path(&mut self, path: &str) -> bool {
+        let Some(index) = self.paths.iter().position(|watched| watched == path) else {
+            return false;
+        };
+
+        self.paths.remove(index);
+        if let Some(rescanner) = self.rescanner.lock().unwrap().as_mut() {
+            rescanner.remove_root(&canonical(path));
+        }
+        // Compared against the paths FSEvents reports.
+        self.restart(vec![canonical(path)]);
+        true
+    }
+
+    fn restart(&mut self, removed: Vec<PathBuf>) {
+        // Whatever the current stream still holds back for its latency is delivered now.
+        let since = match self.stream.as_ref() {
+            Some(stream) if stream.is_started => {
+                stream.flush();
+                stream.get_latest_event_id()
+            }
+            Some(stream) => stream.get_latest_event_id(),
+            None => 0,
+        };
+        let since = if since == 0 { unsafe { get_current_event_id() } } else { since };
+
+        let generation = self.handover.lock().unwrap().restart(since, removed);
+
+        let mut previous = self.stream.take();
+        if !self.paths.is_empty() {
+            let mut stream = self.create_stream(FSEventStreamPointInTime::Since(since), generation);
+            if !self.excluded.is_empty() {
+                let excluded: Vec<&str> = self.excluded.iter().map(String::as_str).collect();
+                // Only fails on a started stream; a refusal by the kernel still excludes.
+                let _ = stream.exclude_paths(excluded);
+            }
+            if self.is_started {
+                stream.start();
+            }
+            self.stream = Some(stream);
+        }
+
+        if let Some(previous) = previous.as_mut() {
+            if previous.is_started {
+                previous.stop();
+            }
+            previous.invalidate();
+        }
+    }
+
+    fn create_stream(&self, since_when: FSEventStreamPointInTime, generation: Generation) -> FileSystemEventStream<'a> {
+        let callback = self.callback.clone();
+        let handover = self.handover.clone();
+        let barrier = self.barrier.clone();
+        let rescanner = self.rescanner.clone();
+
+        let has_file_roots = self.paths.iter().any(|path| !Path::new(path).is_dir());
+        let (paths, roots): (Vec<String>, Option<Vec<PathBuf>>) = if self.uses_cookies() && has_file_roots {
+            let paths = SyncBarrier::watch_paths(&self.paths).iter().map(|path| path.to_string_lossy().into_owned()).collect();
+            (paths, Some(self.paths.iter().map(|path| canonical(path)).collect()))
+        } else {
+            (self.paths.clone(), None)
+        };
+
+        let mut context = FileSystemEventStreamContext::init(None);
+        let paths: Vec<&str> = paths.iter().map(String::as_str).collect();
+        let mut stream = FileSystemEventStream::new(
+            &paths,
+            since_when,
+            self.latency,
+            self.flags,
+            move |_, _, event_paths, event_flags, event_ids| {
+                let events = FsEvent::from_stream(event_paths, event_flags, event_ids);
+                let events = handover.lock().unwrap().process(generation, events);
+                let events = match rescanner.lock().unwrap().as_mut() {
+                    Some(rescanner) => rescanner.process(events),
+                    None => events,
+                };
+                barrier.deliver(events, |events| match roots.as_ref() {
+                    Some(roots) => {
+                        let events = SyncBarrier::within(roots, events);
+                        if !events.is_empty() {
+                            callback(events);
+                        }
+                    }
+                    None => callback(events),
+                });
+            },
+            &mut context,
+        );
+        stream.set_dispatch_queue(self.queue);
+        stream
+    }
+}
+
+impl<'a> Drop for Watcher<'a> {
+    fn drop(&mut self) {
+        if let Some(stream) = self.stream.as_mut() {
+            if stream.is_started {
+                stream.stop();
+            }
+            stream.invalidate();
+        }
+    }
+}
+
+// FSEvents reports paths with symlinks resolved (/private/tmp for /tmp).
+fn canonical(path: &str) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| Path::new(path).to_path_buf())
+}
@@ -0,0 +1,218 @@
+use std::any::Any;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll, Wake, Waker};
+use crate::queue::Queue;
+
+type TaskResult<T> = Result<T, Box<dyn Any + Send + 'static>>;
+
+struct CatchUnwind<F> where F: Future {
+    future: Pin<Box<F>>,
+}
+
+impl<F> Future for CatchUnwind<F> where F: Future {
+    type Output = TaskResult<F::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let future = self.future.as_mut();
+        match panic::catch_unwind(AssertUnwindSafe(|| future.poll(cx))) {
+            Ok(Poll::Pending) => Poll::Pending,
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Err(payload) => Poll::Ready(Err(payload)),
+        }
+    }
+}
+
+struct Task {
+    future: Mutex<Option<Pin<Box<dyn Future<Output = ()> + Send>>>>,
+    queue: Queue,
+    scheduled: AtomicBool,
+}
+
+impl Task {
+    fn schedule(self: Arc<Self>) {
+        if self.scheduled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        let queue = self.queue.clone();
+        queue.dispatch_async(move || self.run());
+    }
+
+    fn run(self: Arc<Self>) {
+        self.scheduled.store(false, Ordering::Release);
+
+        let waker = Waker::from(self.clone());
+        let mut context = Context::from_waker(&waker);
+        let mut future = self.future.lock().unwrap();
+
+        if let Some(pending) = future.as_mut() {
+            if pending.as_mut().poll(&mut context).is_ready() {
+                *future = None;
+            }
+        }
+    }
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        self.schedule();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.clone().schedule();
+    }
+}
+
+struct JoinState<T> {
+    result: Option<TaskResult<T>>,
+    waker: Option<Waker>,
+}
+
+struct JoinShared<T> {
+    state: Mutex<JoinState<T>>,
+    condvar: Condvar,
+}
+
+impl<T> JoinShared<T> {
+    fn complete(&self, result: TaskResult<T>) {
+        let waker = {
+            let mut state = self.state.lock().unwrap();
+            state.result = Some(result);
+            self.condvar.notify_all();
+            state.waker.take()
+        };
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+pub struct JoinHandle<T> {
+    shared: Arc<JoinShared<T>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn is_finished(&self) -> bool {
+        self.shared.state.lock().unwrap().result.is_some()
+    }
+
+    // Blocks the calling thread; never call this from the queue the task runs on if that
+    // queue is serial, since the task could then never be polled again.
+    pub fn join(self) -> T {
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if let Some(result) = state.result.take() {
+                return unwrap_result(result);
+            }
+            state = self.shared.condvar.wait(state).unwrap();
+        }
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut state = self.shared.state.lock().unwrap();
+        match state.result.take() {
+            Some(result) => Poll::Ready(unwrap_result(result)),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+fn unwrap_result<T>(result: TaskResult<T>) -> T {
+    match result {
+        Ok(output) => output,
+        Err(payload) => panic::resume_unwind(payload),
+    }
+}
+
+pub(crate) fn spawn<F>(queue: &Queue, future: F) -> JoinHandle<F::Output>
+    where F: 'static + Send + Future, F::Output: 'static + Send
+{
+    let shared = Arc::new(JoinShared {
+        state: Mutex::new(JoinState { result: None, waker: None }),
+        condvar: Condvar::new(),
+    });
+
+    let completion = shared.clone();
+    let future = CatchUnwind { future: Box::pin(future) };
+    let task = Arc::new(Task {
+        future: Mutex::new(Some(Box::pin(async move {
+            completion.complete(future.await);
+        }))),
+        queue: queue.clone(),
+        scheduled: AtomicBool::new(false),
+    });
+
+    task.schedule();
+
+    JoinHandle { shared }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::attr::QueueAttr;
+
+    struct YieldOnce {
+        yielded: bool,
+    }
+
+    impl Future for YieldOnce {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.yielded {
+                return Poll::Ready(());
+            }
+
+            self.yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn test_spawn_and_join() {
+        let queue = Queue::create("com.example.myqueue", QueueAttr::Serial);
+        let handle = queue.spawn(async { 40 + 2 });
+        assert_eq!(handle.join(), 42);
+    }
+
+    #[test]
+    fn test_spawn_reschedules_on_wake() {
+        let queue = Queue::create("com.example.myqueue", QueueAttr::Concurrent);
+        let handle = queue.spawn(async {
+            YieldOnce { yielded: false }.await;
+            YieldOnce { yielded: false }.await;
+            "done"
+        });
+        assert_eq!(handle.join(), "done");
+    }
+
+    #[test]
+    fn test_await_join_handle() {
+        let queue = Queue::create("com.example.myqueue", QueueAttr::Serial);
+        let inner = queue.spawn(async { 21 });
+        let outer = queue.spawn(async move { inner.await * 2 });
+        assert_eq!(outer.join(), 42);
+    }
+
+    #[test]
+    fn test_join_propagates_panic() {
+        let queue = Queue::create("com.example.myqueue", QueueAttr::Serial);
+        let handle = queue.spawn(async { panic!("task failed") });
+        let result = panic::catch_unwind(AssertUnwindSafe(|| handle.join()));
+        assert!(result.is_err());
+    }
+}
@@ -1,7 +1,7 @@
 #![allow(missing_docs)]
 #![allow(non_camel_case_types)]
 
-use std::ffi::{c_char, c_long, c_ulong, c_void};
+use std::ffi::{c_char, c_int, c_long, c_uint, c_ulong, c_void};
 
 #[repr(C)]
 pub struct dispatch_object_s { _private: [u8; 0] }
@@ -14,6 +14,8 @@ pub type dispatch_once_t = c_long;
 pub type dispatch_queue_t = *mut dispatch_object_s;
 pub type dispatch_time_t = u64;
 pub type dispatch_queue_attr_t = *const dispatch_object_s;
+pub type dispatch_qos_class_t = c_uint;
+pub type dispatch_autorelease_frequency_t = c_ulong;
 
 extern {
     static _dispatch_main_q: dispatch_object_s;
@@ -22,7 +24,14 @@ extern {
     pub fn dispatch_get_global_queue(identifier: c_long, flags: c_ulong) -> dispatch_queue_t;
     pub fn dispatch_queue_create(label: *const c_char, attr: dispatch_queue_attr_t) -> dispatch_queue_t;
     pub fn dispatch_queue_get_label(queue: dispatch_queue_t) -> *const c_char;
+    pub fn dispatch_queue_get_qos_class(queue: dispatch_queue_t, relative_priority: *mut c_int) -> dispatch_qos_class_t;
+    pub fn dispatch_queue_attr_make_with_qos_class(attr: dispatch_queue_attr_t, qos_class: dispatch_qos_class_t, relative_priority: c_int) -> dispatch_queue_attr_t;
+    pub fn dispatch_queue_attr_make_with_autorelease_frequency(attr: dispatch_queue_attr_t, frequency: dispatch_autorelease_frequency_t) -> dispatch_queue_attr_t;
+    pub fn dispatch_queue_attr_make_with_overcommit(attr: dispatch_queue_attr_t, overcommit: bool) -> dispatch_queue_attr_t;
     pub fn dispatch_set_target_queue(object: dispatch_object_t, queue: dispatch_queue_t);
+    pub fn dispatch_queue_set_specific(queue: dispatch_queue_t, key: *const c_void, context: *mut c_void, destructor: Option<dispatch_function_t>);
+    pub fn dispatch_queue_get_specific(queue: dispatch_queue_t, key: *const c_void) -> *mut c_void;
+    pub fn dispatch_get_specific(key: *const c_void) -> *mut c_void;
     pub fn dispatch_main();
 
     pub fn dispatch_async_f(queue: dispatch_queue_t, context: *mut c_void, work: dispatch_function_t);
@@ -69,5 +78,17 @@ pub const DISPATCH_QUEUE_PRIORITY_DEFAULT: c_long    = 0;
 pub const DISPATCH_QUEUE_PRIORITY_LOW: c_long        = -2;
 pub const DISPATCH_QUEUE_PRIORITY_BACKGROUND: c_long = -1 << 15;
 
+pub const QOS_CLASS_USER_INTERACTIVE: dispatch_qos_class_t = 0x21;
+pub const QOS_CLASS_USER_INITIATED: dispatch_qos_class_t   = 0x19;
+pub const QOS_CLASS_DEFAULT: dispatch_qos_class_t          = 0x15;
+pub const QOS_CLASS_UTILITY: dispatch_qos_class_t          = 0x11;
+pub const QOS_CLASS_BACKGROUND: dispatch_qos_class_t       = 0x09;
+pub const QOS_CLASS_UNSPECIFIED: dispatch_qos_class_t      = 0x00;
+pub const QOS_MIN_RELATIVE_PRIORITY: c_int                 = -15;
+
+pub const DISPATCH_AUTORELEASE_FREQUENCY_INHERIT: dispatch_autorelease_frequency_t   = 0;
+pub const DISPATCH_AUTORELEASE_FREQUENCY_WORK_ITEM: dispatch_autorelease_frequency_t = 1;
+pub const DISPATCH_AUTORELEASE_FREQUENCY_NEVER: dispatch_autorelease_frequency_t     = 2;
+
 pub const DISPATCH_TIME_NOW: dispatch_time_t     = 0;
 pub const DISPATCH_TIME_FOREVER: dispatch_time_t = !0;
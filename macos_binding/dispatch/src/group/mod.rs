@@ -2,7 +2,10 @@
 
 use std::time::Duration;
 use crate::ffi::{dispatch_group_async_f, dispatch_group_create, dispatch_group_enter, dispatch_group_leave, dispatch_group_notify_f, dispatch_group_t, dispatch_group_wait, dispatch_release, dispatch_resume, dispatch_retain, dispatch_suspend, DISPATCH_TIME_FOREVER};
-use crate::queue::Queue;
+use crate::queue::{metrics, Queue};
+#[cfg(target_os = "linux")]
+use crate::queue::qos;
+use crate::work_item::WorkItem;
 use crate::r#fn::{get_context_and_async_fn, get_time_after_delay};
 
 pub struct Group {
@@ -19,12 +22,17 @@ impl Group {
         Group { ptr }
     }
 
-    pub fn exec_async<F>(&self, queue: Queue, work: F)
+    pub fn exec_async<F>(&self, queue: Queue, work: F) -> WorkItem
         where F: 'static + Send + FnOnce() {
+        let (item, work) = WorkItem::wrap(work);
+        #[cfg(target_os = "linux")]
+        let work = qos::apply_niceness(&queue, work);
+        let work = metrics::instrument(&queue, Duration::ZERO, work);
         let (context, work) = get_context_and_async_fn(work);
         unsafe {
             dispatch_group_async_f(self.ptr, queue.ptr, context, work);
         }
+        item
     }
 
     pub fn notify<F>(&self, queue: Queue, work: F)
@@ -1,4 +1,7 @@
 pub mod ffi;
 pub mod queue;
 pub(crate) mod r#fn;
-mod group;
\ No newline at end of file
+mod group;
+pub mod work_item;
+pub mod executor;
+pub mod permission;
@@ -0,0 +1,158 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+use abstr::permission::{Decision, PermissionEvent, PermissionRequest};
+#[cfg(target_os = "linux")]
+use abstr::permission::PermissionListener;
+use crate::queue::Queue;
+use crate::queue::attr::QueueAttr;
+use crate::queue::qos::QosClass;
+
+type Callback = dyn Fn(&PermissionEvent) -> Decision + Send + Sync;
+
+// Answers permission requests (fanotify's FAN_*_PERM events) with a user callback that
+// runs on a queue of its own. The kernel blocks the operation until it is answered, so a
+// request the callback did not decide within the deadline, or that it panicked on, gets
+// the default decision instead; the callback's late answer is then dropped.
+pub struct PermissionGate {
+    queue: Queue,
+    timer: Queue,
+    deadline: Duration,
+    default: Decision,
+    callback: Arc<Callback>,
+}
+
+struct Pending<R> {
+    request: PermissionRequest,
+    respond: R,
+}
+
+impl PermissionGate {
+    pub fn new<F>(label: &str, deadline: Duration, default: Decision, callback: F) -> Self
+        where F: 'static + Send + Sync + Fn(&PermissionEvent) -> Decision
+    {
+        PermissionGate {
+            queue: Queue::create(label, QueueAttr::Concurrent),
+            // Timeouts must fire even when every callback on `queue` is stuck.
+            timer: Queue::global_with_qos(QosClass::UserInteractive),
+            deadline,
+            default,
+            callback: Arc::new(callback),
+        }
+    }
+
+    pub fn queue(&self) -> &Queue {
+        &self.queue
+    }
+
+    pub fn deadline(&self) -> Duration {
+        self.deadline
+    }
+
+    pub fn default_decision(&self) -> Decision {
+        self.default
+    }
+
+    // `respond` is called exactly once, either with the callback's decision or with the
+    // default when the deadline passed first.
+    pub fn submit<R>(&self, request: PermissionRequest, respond: R)
+        where R: 'static + Send + FnOnce(PermissionRequest, Decision)
+    {
+        let event = request.event.clone();
+        let pending = Arc::new(Mutex::new(Some(Pending { request, respond })));
+        let default = self.default;
+
+        let timed_out = pending.clone();
+        let timeout = self.timer.dispatch_async_after(move || {
+            if let Some(pending) = timed_out.lock().unwrap().take() {
+                (pending.respond)(pending.request, default);
+            }
+        }, self.deadline);
+
+        let callback = self.callback.clone();
+        self.queue.dispatch_async(move || {
+            // The callback may block for a long time; it must not hold up the timer.
+            if pending.lock().unwrap().is_none() {
+                return;
+            }
+            let decision = panic::catch_unwind(AssertUnwindSafe(|| callback(&event))).unwrap_or(default);
+
+            if let Some(pending) = pending.lock().unwrap().take() {
+                // Answered in time, the timeout has nothing left to do.
+                timeout.cancel();
+                (pending.respond)(pending.request, decision);
+            }
+        });
+    }
+
+    // Answers what `listener` asks about until reading from it fails; blocks the calling
+    // thread, the callback runs on `queue`.
+    #[cfg(target_os = "linux")]
+    pub fn serve(&self, listener: PermissionListener) -> std::io::Result<()> {
+        let listener = Arc::new(listener);
+        loop {
+            for request in listener.read()? {
+                let responder = listener.clone();
+                self.submit(request, move |request, decision| {
+                    // The kernel gives up on the request itself when the answer cannot be written.
+                    let _ = responder.respond(request, decision);
+                });
+            }
+        }
+    }
+
+    // Blocks the calling thread until the request is decided.
+    pub fn decide(&self, request: PermissionRequest) -> Decision {
+        let answer = Arc::new((Mutex::new(None), Condvar::new()));
+
+        let answered = answer.clone();
+        self.submit(request, move |_, decision| {
+            let (decision_lock, condvar) = &*answered;
+            *decision_lock.lock().unwrap() = Some(decision);
+            condvar.notify_all();
+        });
+
+        let (decision_lock, condvar) = &*answer;
+        let decision = condvar.wait_while(decision_lock.lock().unwrap(), |decision| decision.is_none()).unwrap();
+        decision.unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::path::PathBuf;
+    use abstr::permission::PermissionKind;
+
+    fn request(path: &str) -> PermissionRequest {
+        PermissionRequest {
+            event: PermissionEvent { path: PathBuf::from(path), kind: PermissionKind::Open, pid: Some(1) },
+            fd: File::open("/dev/null").unwrap().into(),
+        }
+    }
+
+    #[test]
+    fn test_callback_decides() {
+        let gate = PermissionGate::new("com.example.permission", Duration::from_secs(1), Decision::Allow, |request| {
+            if request.path.starts_with("/quarantine") { Decision::Deny } else { Decision::Allow }
+        });
+
+        assert_eq!(gate.decide(request("/quarantine/file")), Decision::Deny);
+        assert_eq!(gate.decide(request("/data/file")), Decision::Allow);
+    }
+
+    #[test]
+    fn test_default_on_timeout_and_panic() {
+        let slow = PermissionGate::new("com.example.permission.slow", Duration::from_millis(10), Decision::Deny, |_| {
+            std::thread::sleep(Duration::from_millis(200));
+            Decision::Allow
+        });
+        assert_eq!(slow.decide(request("/data/file")), Decision::Deny);
+
+        let panicking = PermissionGate::new("com.example.permission.panic", Duration::from_secs(1), Decision::Deny, |_| {
+            panic!("callback failed")
+        });
+        assert_eq!(panicking.decide(request("/data/file")), Decision::Deny);
+    }
+}
@@ -1,5 +1,8 @@
-use crate::ffi::{dispatch_queue_attr_t, DISPATCH_QUEUE_CONCURRENT, DISPATCH_QUEUE_SERIAL};
+use crate::ffi::{dispatch_autorelease_frequency_t, dispatch_queue_attr_make_with_autorelease_frequency, dispatch_queue_attr_make_with_overcommit, dispatch_queue_attr_make_with_qos_class, dispatch_queue_attr_t, DISPATCH_AUTORELEASE_FREQUENCY_INHERIT, DISPATCH_AUTORELEASE_FREQUENCY_NEVER, DISPATCH_AUTORELEASE_FREQUENCY_WORK_ITEM, DISPATCH_QUEUE_CONCURRENT, DISPATCH_QUEUE_SERIAL};
+use crate::queue::error::InvalidRawValue;
+use crate::queue::qos::Qos;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum QueueAttr {
     Serial,
     Concurrent,
@@ -13,10 +16,134 @@ impl QueueAttr {
         }
     }
 
-    pub fn from_raw(raw: dispatch_queue_attr_t) -> Self {
+    pub fn from_raw(raw: dispatch_queue_attr_t) -> Result<Self, InvalidRawValue<dispatch_queue_attr_t>> {
+        if raw == DISPATCH_QUEUE_SERIAL {
+            Ok(QueueAttr::Serial)
+        } else if std::ptr::eq(raw, DISPATCH_QUEUE_CONCURRENT) {
+            Ok(QueueAttr::Concurrent)
+        } else {
+            Err(InvalidRawValue::new("QueueAttr", raw))
+        }
+    }
+
+    pub fn with_qos<Q>(self, qos: Q) -> QueueAttributes where Q: Into<Qos> {
+        QueueAttributes::from(self).with_qos(qos)
+    }
+
+    pub fn with_autorelease_frequency(self, frequency: AutoreleaseFrequency) -> QueueAttributes {
+        QueueAttributes::from(self).with_autorelease_frequency(frequency)
+    }
+
+    pub fn with_overcommit(self, overcommit: bool) -> QueueAttributes {
+        QueueAttributes::from(self).with_overcommit(overcommit)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AutoreleaseFrequency {
+    Inherit,
+    WorkItem,
+    Never,
+}
+
+impl AutoreleaseFrequency {
+    pub fn to_raw(&self) -> dispatch_autorelease_frequency_t {
+        match self {
+            AutoreleaseFrequency::Inherit => DISPATCH_AUTORELEASE_FREQUENCY_INHERIT,
+            AutoreleaseFrequency::WorkItem => DISPATCH_AUTORELEASE_FREQUENCY_WORK_ITEM,
+            AutoreleaseFrequency::Never => DISPATCH_AUTORELEASE_FREQUENCY_NEVER,
+        }
+    }
+
+    pub fn from_raw(raw: dispatch_autorelease_frequency_t) -> Result<Self, InvalidRawValue<dispatch_autorelease_frequency_t>> {
         match raw {
-            DISPATCH_QUEUE_SERIAL => QueueAttr::Serial,
-            _ => panic!("Invalid raw value for QueueAttr"),
+            DISPATCH_AUTORELEASE_FREQUENCY_INHERIT => Ok(AutoreleaseFrequency::Inherit),
+            DISPATCH_AUTORELEASE_FREQUENCY_WORK_ITEM => Ok(AutoreleaseFrequency::WorkItem),
+            DISPATCH_AUTORELEASE_FREQUENCY_NEVER => Ok(AutoreleaseFrequency::Never),
+            _ => Err(InvalidRawValue::new("AutoreleaseFrequency", raw)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueueAttributes {
+    pub kind: QueueAttr,
+    pub qos: Option<Qos>,
+    pub autorelease_frequency: Option<AutoreleaseFrequency>,
+    pub overcommit: Option<bool>,
+}
+
+impl QueueAttributes {
+    pub fn is_serial(&self) -> bool {
+        self.kind == QueueAttr::Serial
+    }
+
+    pub fn with_qos<Q>(mut self, qos: Q) -> Self where Q: Into<Qos> {
+        self.qos = Some(qos.into());
+        self
+    }
+
+    pub fn with_autorelease_frequency(mut self, frequency: AutoreleaseFrequency) -> Self {
+        self.autorelease_frequency = Some(frequency);
+        self
+    }
+
+    pub fn with_overcommit(mut self, overcommit: bool) -> Self {
+        self.overcommit = Some(overcommit);
+        self
+    }
+
+    pub fn to_raw(&self) -> dispatch_queue_attr_t {
+        let mut raw = self.kind.to_raw();
+
+        if let Some(frequency) = self.autorelease_frequency {
+            raw = unsafe { dispatch_queue_attr_make_with_autorelease_frequency(raw, frequency.to_raw()) };
         }
+
+        if let Some(overcommit) = self.overcommit {
+            raw = unsafe { dispatch_queue_attr_make_with_overcommit(raw, overcommit) };
+        }
+
+        if let Some(qos) = self.qos {
+            raw = unsafe { dispatch_queue_attr_make_with_qos_class(raw, qos.class.to_raw(), qos.relative_priority) };
+        }
+
+        raw
     }
-}
\ No newline at end of file
+}
+
+impl From<QueueAttr> for QueueAttributes {
+    fn from(kind: QueueAttr) -> Self {
+        QueueAttributes { kind, qos: None, autorelease_frequency: None, overcommit: None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::qos::QosClass;
+
+    #[test]
+    fn test_queue_attr_round_trip() {
+        assert_eq!(QueueAttr::from_raw(QueueAttr::Serial.to_raw()), Ok(QueueAttr::Serial));
+        assert_eq!(QueueAttr::from_raw(QueueAttr::Concurrent.to_raw()), Ok(QueueAttr::Concurrent));
+    }
+
+    #[test]
+    fn test_combine_attributes() {
+        let attributes = QueueAttr::Concurrent
+            .with_qos(Qos::new(QosClass::Utility, -4))
+            .with_autorelease_frequency(AutoreleaseFrequency::WorkItem)
+            .with_overcommit(false);
+
+        assert!(!attributes.is_serial());
+        assert_eq!(attributes.qos, Some(Qos::new(QosClass::Utility, -4)));
+        assert_eq!(attributes.autorelease_frequency, Some(AutoreleaseFrequency::WorkItem));
+        assert_eq!(attributes.overcommit, Some(false));
+    }
+
+    #[test]
+    fn test_autorelease_frequency_from_unknown_raw() {
+        assert!(AutoreleaseFrequency::from_raw(7).is_err());
+    }
+}
@@ -0,0 +1,146 @@
+use std::cell::RefCell;
+use std::mem::ManuallyDrop;
+use crate::ffi::{dispatch_queue_get_specific, dispatch_queue_t};
+use crate::queue::Queue;
+use crate::queue::specific::{self, QueueKey};
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct QueueIdentity {
+    pub(crate) ptr: usize,
+    pub(crate) serial: bool,
+    pub(crate) target: Option<usize>,
+}
+
+pub(crate) static IDENTITY_KEY: QueueKey<QueueIdentity> = QueueKey::new();
+
+thread_local! {
+    // The queues held by the synchronous callers that are waiting, directly or through
+    // other synchronous submissions, for the work item running on this thread.
+    static WAITING: RefCell<Vec<QueueIdentity>> = const { RefCell::new(Vec::new()) };
+}
+
+pub(crate) fn register(queue: &Queue, serial: bool) {
+    queue.set_specific(&IDENTITY_KEY, QueueIdentity { ptr: queue.ptr as usize, serial, target: None });
+}
+
+pub(crate) fn retarget(queue: &Queue, target: &Queue) {
+    if let Some(identity) = queue.get_specific(&IDENTITY_KEY) {
+        let mut identity = (*identity).clone();
+        identity.target = Some(target.ptr as usize);
+        queue.set_specific(&IDENTITY_KEY, identity);
+    }
+}
+
+fn chain(ptr: usize) -> Vec<QueueIdentity> {
+    let mut chain: Vec<QueueIdentity> = Vec::new();
+    let mut next = Some(ptr);
+
+    while let Some(ptr) = next {
+        if chain.iter().any(|identity| identity.ptr == ptr) {
+            break;
+        }
+
+        let identity = unsafe {
+            specific::read(&IDENTITY_KEY, || dispatch_queue_get_specific(ptr as dispatch_queue_t, IDENTITY_KEY.as_ptr()))
+        };
+        let identity = match identity {
+            Some(identity) => (*identity).clone(),
+            None => break,
+        };
+        next = identity.target;
+        chain.push(identity);
+    }
+
+    chain
+}
+
+// Every serial queue in the chain we are running on is held for the duration of the
+// current work item, so a synchronous submission that has to pass through one of them
+// can never start.
+pub(crate) fn would_deadlock(current: &[QueueIdentity], destination: &[QueueIdentity]) -> Option<usize> {
+    destination
+        .iter()
+        .filter(|identity| identity.serial)
+        .find(|identity| current.iter().any(|held| held.serial && held.ptr == identity.ptr))
+        .map(|identity| identity.ptr)
+}
+
+// Panics when `work` could never run, and otherwise wraps it so that the queues held up
+// to here are known while it runs, on whichever thread libdispatch picks for it.
+pub(crate) fn check_sync<T, F>(queue: &Queue, operation: &str, work: F) -> impl FnOnce() -> T
+    where F: FnOnce() -> T
+{
+    let mut held = WAITING.with(|waiting| waiting.borrow().clone());
+    if let Some(current) = Queue::get_current_specific(&IDENTITY_KEY) {
+        held.extend(chain(current.ptr));
+    }
+
+    if let Some(ptr) = would_deadlock(&held, &chain(queue.ptr as usize)) {
+        let held = ManuallyDrop::new(Queue { ptr: ptr as dispatch_queue_t });
+        panic!(
+            "{} onto queue \"{}\" would deadlock: serial queue \"{}\" is held by the current work item or a caller waiting for it",
+            operation,
+            queue.label(),
+            held.label(),
+        );
+    }
+
+    move || {
+        let outer = WAITING.with(|waiting| waiting.replace(held));
+        let _restore = Restore(Some(outer));
+        work()
+    }
+}
+
+struct Restore(Option<Vec<QueueIdentity>>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        if let Some(outer) = self.0.take() {
+            WAITING.with(|waiting| *waiting.borrow_mut() = outer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(ptr: usize, serial: bool, target: Option<usize>) -> QueueIdentity {
+        QueueIdentity { ptr, serial, target }
+    }
+
+    #[test]
+    fn test_would_deadlock_on_same_serial_queue() {
+        let serial = [identity(1, true, None)];
+        assert_eq!(would_deadlock(&serial, &serial), Some(1));
+    }
+
+    #[test]
+    fn test_would_not_deadlock_on_concurrent_queue() {
+        let concurrent = [identity(1, false, None)];
+        assert_eq!(would_deadlock(&concurrent, &concurrent), None);
+    }
+
+    #[test]
+    fn test_would_deadlock_through_target_chain() {
+        let current = [identity(1, true, Some(3)), identity(3, true, None)];
+        let destination = [identity(2, false, Some(3)), identity(3, true, None)];
+        assert_eq!(would_deadlock(&current, &destination), Some(3));
+    }
+
+    #[test]
+    fn test_would_deadlock_on_queue_held_by_waiting_caller() {
+        // X -> sync Y -> sync X: the work on Y runs while X waits for it.
+        let held = [identity(2, true, None), identity(1, true, None)];
+        let destination = [identity(1, true, None)];
+        assert_eq!(would_deadlock(&held, &destination), Some(1));
+    }
+
+    #[test]
+    fn test_would_not_deadlock_on_unrelated_queue() {
+        let current = [identity(1, true, Some(3)), identity(3, false, None)];
+        let destination = [identity(2, true, Some(3)), identity(3, false, None)];
+        assert_eq!(would_deadlock(&current, &destination), None);
+    }
+}
@@ -0,0 +1,40 @@
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use crate::ffi::QOS_MIN_RELATIVE_PRIORITY;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidRawValue<T> {
+    pub type_name: &'static str,
+    pub raw: T,
+}
+
+impl<T> InvalidRawValue<T> {
+    pub(crate) fn new(type_name: &'static str, raw: T) -> Self {
+        InvalidRawValue { type_name, raw }
+    }
+}
+
+impl<T> Display for InvalidRawValue<T> where T: Debug {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid raw value for {}: {:?}", self.type_name, self.raw)
+    }
+}
+
+impl<T> Error for InvalidRawValue<T> where T: Debug {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueueError {
+    InvalidRelativePriority(i32),
+}
+
+impl Display for QueueError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueueError::InvalidRelativePriority(relative_priority) => {
+                write!(f, "Relative priority must be between {} and 0, got {}", QOS_MIN_RELATIVE_PRIORITY, relative_priority)
+            }
+        }
+    }
+}
+
+impl Error for QueueError {}
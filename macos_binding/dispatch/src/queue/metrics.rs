@@ -0,0 +1,269 @@
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use crate::queue::Queue;
+use crate::queue::specific::QueueKey;
+
+const BUCKETS: usize = 32;
+
+static METRICS_KEY: QueueKey<QueueMetrics> = QueueKey::new();
+static REGISTRY: Mutex<Vec<Weak<QueueMetrics>>> = Mutex::new(Vec::new());
+// Set once any queue has metrics, so that submissions do not look them up before.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// Bucket 0 holds everything under a microsecond, bucket `i` durations below 2^i
+// microseconds and the last bucket everything that is longer.
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKETS],
+    count: AtomicU64,
+    total_nanos: AtomicU64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Histogram {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            count: AtomicU64::new(0),
+            total_nanos: AtomicU64::new(0),
+        }
+    }
+
+    fn bucket_for(duration: Duration) -> usize {
+        let micros = duration.as_micros();
+        if micros == 0 {
+            return 0;
+        }
+
+        ((u128::BITS - micros.leading_zeros()) as usize).min(BUCKETS - 1)
+    }
+
+    fn upper_bound(bucket: usize) -> Option<Duration> {
+        if bucket == BUCKETS - 1 {
+            None
+        } else {
+            Some(Duration::from_micros(1 << bucket))
+        }
+    }
+
+    pub(crate) fn record(&self, duration: Duration) {
+        self.buckets[Self::bucket_for(duration)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_nanos.fetch_add(duration.as_nanos().min(u64::MAX as u128) as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        HistogramSnapshot {
+            buckets: self.buckets
+                .iter()
+                .enumerate()
+                .map(|(bucket, count)| (Self::upper_bound(bucket), count.load(Ordering::Relaxed)))
+                .collect(),
+            count: self.count.load(Ordering::Relaxed),
+            total: Duration::from_nanos(self.total_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistogramSnapshot {
+    pub buckets: Vec<(Option<Duration>, u64)>,
+    pub count: u64,
+    pub total: Duration,
+}
+
+impl HistogramSnapshot {
+    pub fn mean(&self) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+
+        Some(Duration::from_nanos((self.total.as_nanos() / self.count as u128) as u64))
+    }
+
+    // Returns the upper bound of the bucket holding the given percentile, `None` when
+    // it falls into the unbounded last bucket or nothing was recorded.
+    pub fn percentile(&self, percentile: f64) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+
+        let rank = ((percentile / 100.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (upper_bound, count) in &self.buckets {
+            seen += count;
+            if seen >= rank {
+                return *upper_bound;
+            }
+        }
+
+        None
+    }
+}
+
+pub struct QueueMetrics {
+    label: String,
+    submitted: AtomicU64,
+    completed: AtomicU64,
+    wait_time: Histogram,
+    execution_time: Histogram,
+}
+
+impl QueueMetrics {
+    fn new(label: String) -> Self {
+        QueueMetrics {
+            label,
+            submitted: AtomicU64::new(0),
+            completed: AtomicU64::new(0),
+            wait_time: Histogram::new(),
+            execution_time: Histogram::new(),
+        }
+    }
+
+    pub fn snapshot(&self) -> QueueMetricsSnapshot {
+        let completed = self.completed.load(Ordering::Acquire);
+        let submitted = self.submitted.load(Ordering::Acquire);
+
+        QueueMetricsSnapshot {
+            label: self.label.clone(),
+            submitted,
+            completed,
+            in_flight: submitted.saturating_sub(completed),
+            wait_time: self.wait_time.snapshot(),
+            execution_time: self.execution_time.snapshot(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueueMetricsSnapshot {
+    pub label: String,
+    pub submitted: u64,
+    pub completed: u64,
+    pub in_flight: u64,
+    pub wait_time: HistogramSnapshot,
+    pub execution_time: HistogramSnapshot,
+}
+
+struct CompletionGuard<'a> {
+    metrics: &'a QueueMetrics,
+    started: Instant,
+}
+
+impl<'a> Drop for CompletionGuard<'a> {
+    fn drop(&mut self) {
+        self.metrics.execution_time.record(self.started.elapsed());
+        self.metrics.completed.fetch_add(1, Ordering::Release);
+    }
+}
+
+pub(crate) fn enable(queue: &Queue) {
+    // Checked and installed under the registry lock so that concurrent calls agree on
+    // one collector.
+    let mut registry = REGISTRY.lock().unwrap();
+    if queue.get_specific(&METRICS_KEY).is_some() {
+        return;
+    }
+
+    let metrics = Arc::new(QueueMetrics::new(queue.label()));
+    registry.push(Arc::downgrade(&metrics));
+    queue.set_specific(&METRICS_KEY, metrics);
+    ENABLED.store(true, Ordering::Release);
+}
+
+pub(crate) fn get(queue: &Queue) -> Option<QueueMetricsSnapshot> {
+    queue.get_specific(&METRICS_KEY).map(|metrics| metrics.snapshot())
+}
+
+// Wraps a work item so that it is counted against the queue it is submitted to. `delay`
+// is the time the item is not eligible to run yet and does not count as waiting.
+pub(crate) fn instrument<T, F>(queue: &Queue, delay: Duration, work: F) -> impl FnOnce() -> T
+    where F: FnOnce() -> T
+{
+    let metrics = if ENABLED.load(Ordering::Acquire) { queue.get_specific(&METRICS_KEY) } else { None };
+    let submission = metrics.map(|metrics| {
+        metrics.submitted.fetch_add(1, Ordering::Release);
+        (metrics, Instant::now() + delay)
+    });
+
+    move || match submission {
+        None => work(),
+        Some((metrics, eligible_at)) => {
+            let started = Instant::now();
+            metrics.wait_time.record(started.saturating_duration_since(eligible_at));
+            let _guard = CompletionGuard { metrics: &metrics, started };
+            work()
+        }
+    }
+}
+
+// Every queue with metrics enabled that is still alive; the entry goes away together
+// with the last reference to the underlying dispatch queue.
+pub fn snapshot_all() -> Vec<QueueMetricsSnapshot> {
+    let mut registry = REGISTRY.lock().unwrap();
+    registry.retain(|metrics| metrics.strong_count() > 0);
+    registry
+        .iter()
+        .filter_map(|metrics| metrics.upgrade())
+        .map(|metrics| metrics.snapshot())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_buckets() {
+        assert_eq!(Histogram::bucket_for(Duration::from_nanos(500)), 0);
+        assert_eq!(Histogram::bucket_for(Duration::from_micros(1)), 1);
+        assert_eq!(Histogram::bucket_for(Duration::from_micros(3)), 2);
+        assert_eq!(Histogram::bucket_for(Duration::from_micros(4)), 3);
+        assert_eq!(Histogram::bucket_for(Duration::from_secs(100_000)), BUCKETS - 1);
+    }
+
+    #[test]
+    fn test_histogram_snapshot() {
+        let histogram = Histogram::new();
+        histogram.record(Duration::from_micros(3));
+        histogram.record(Duration::from_micros(3));
+        histogram.record(Duration::from_micros(100));
+
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.count, 3);
+        assert_eq!(snapshot.total, Duration::from_micros(106));
+        assert_eq!(snapshot.percentile(50.0), Some(Duration::from_micros(4)));
+        assert_eq!(snapshot.percentile(100.0), Some(Duration::from_micros(128)));
+    }
+
+    #[test]
+    fn test_empty_histogram_snapshot() {
+        let snapshot = Histogram::new().snapshot();
+        assert_eq!(snapshot.mean(), None);
+        assert_eq!(snapshot.percentile(99.0), None);
+    }
+
+    #[test]
+    fn test_queue_metrics() {
+        use crate::queue::attr::QueueAttr;
+
+        let queue = Queue::create("com.example.metrics", QueueAttr::Serial);
+        assert_eq!(queue.metrics(), None);
+
+        queue.enable_metrics();
+        let collector = queue.get_specific(&METRICS_KEY).unwrap();
+        queue.enable_metrics();
+        assert!(Arc::ptr_eq(&collector, &queue.get_specific(&METRICS_KEY).unwrap()));
+        drop(collector);
+
+        queue.dispatch_async(|| ());
+        queue.dispatch_sync(|| ());
+
+        let metrics = queue.metrics().unwrap();
+        assert_eq!(metrics.label, "com.example.metrics");
+        assert_eq!(metrics.submitted, 2);
+        assert_eq!(metrics.completed, 2);
+        assert_eq!(metrics.in_flight, 0);
+        assert_eq!(metrics.execution_time.count, 2);
+        assert!(snapshot_all().iter().any(|snapshot| snapshot.label == "com.example.metrics"));
+    }
+}
@@ -1,15 +1,29 @@
 #![allow(dead_code)]
 
-use std::ffi::{c_long, CStr, CString};
+use std::ffi::{c_int, c_long, CStr, CString};
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
 use std::time::Duration;
 use std::str;
-use crate::ffi::{dispatch_after_f, dispatch_apply_f, dispatch_async_and_wait_f, dispatch_async_f, dispatch_get_global_queue, dispatch_get_main_queue, dispatch_queue_create, dispatch_queue_get_label, dispatch_queue_t, dispatch_release, dispatch_resume, dispatch_retain, dispatch_suspend, dispatch_sync_f};
-use crate::queue::attr::QueueAttr;
+use std::sync::{Arc, Mutex, Once};
+use crate::ffi::{dispatch_after_f, dispatch_apply_f, dispatch_async_and_wait_f, dispatch_async_f, dispatch_get_global_queue, dispatch_get_main_queue, dispatch_get_specific, dispatch_queue_create, dispatch_queue_get_label, dispatch_queue_get_qos_class, dispatch_queue_get_specific, dispatch_queue_set_specific, dispatch_queue_t, dispatch_release, dispatch_resume, dispatch_retain, dispatch_set_target_queue, dispatch_suspend, dispatch_sync_f};
+use crate::queue::attr::QueueAttributes;
+use crate::queue::error::InvalidRawValue;
+use crate::queue::metrics::QueueMetricsSnapshot;
 use crate::queue::priority::QueuePriority;
+use crate::queue::qos::{Qos, QosClass};
+use crate::queue::specific::{self as specific_value, QueueKey};
+use crate::executor::{self, JoinHandle};
+use crate::work_item::WorkItem;
 use crate::r#fn::{get_context_and_apply_fn, get_context_and_async_fn, get_context_and_sync_function, get_time_after_delay};
 
 pub mod priority;
 pub mod attr;
+pub mod specific;
+pub mod qos;
+pub mod error;
+pub mod metrics;
+mod deadlock;
 
 pub struct Queue {
     pub ptr: dispatch_queue_t,
@@ -17,11 +31,15 @@ pub struct Queue {
 
 impl Queue {
     pub fn main() -> Self {
+        static REGISTER_MAIN: Once = Once::new();
+
         let queue = dispatch_get_main_queue();
         unsafe {
             dispatch_retain(queue);
         }
-        Queue { ptr: queue }
+        let queue = Queue { ptr: queue };
+        REGISTER_MAIN.call_once(|| deadlock::register(&queue, true));
+        queue
     }
 
     pub fn global(priority: QueuePriority) -> Self {
@@ -32,12 +50,23 @@ impl Queue {
         }
     }
 
-    pub fn create(label: &str, attr: QueueAttr) -> Self {
+    pub fn global_with_qos(class: QosClass) -> Self {
+        unsafe {
+            let queue = dispatch_get_global_queue(class.to_raw() as c_long, 0);
+            dispatch_retain(queue);
+            Queue { ptr: queue }
+        }
+    }
+
+    pub fn create<A>(label: &str, attr: A) -> Self where A: Into<QueueAttributes> {
+        let attr = attr.into();
         let label = CString::new(label).unwrap();
         let queue = unsafe {
             dispatch_queue_create(label.as_ptr(), attr.to_raw())
         };
-        Queue { ptr: queue }
+        let queue = Queue { ptr: queue };
+        deadlock::register(&queue, attr.is_serial());
+        queue
     }
 
     pub fn label(&self) -> String {
@@ -50,9 +79,19 @@ impl Queue {
         c_str.to_string_lossy().into_owned()
     }
 
+    pub fn qos(&self) -> Result<Qos, InvalidRawValue<u32>> {
+        let mut relative_priority: c_int = 0;
+        let class = unsafe { dispatch_queue_get_qos_class(self.ptr, &mut relative_priority) };
+        Ok(Qos { class: QosClass::from_raw(class)?, relative_priority })
+    }
+
     pub fn dispatch_sync<T, F>(&self, work: F) -> T
         where F: Send + FnOnce() -> T, T: Send
     {
+        #[cfg(debug_assertions)]
+        let work = deadlock::check_sync(self, "dispatch_sync", work);
+
+        let work = metrics::instrument(self, Duration::ZERO, work);
         let mut result = None;
         {
             let result_ref = &mut result;
@@ -72,6 +111,10 @@ impl Queue {
     pub fn dispatch_async_and_wait<T, F>(&self, work: F) -> T
         where F: 'static + Send + FnOnce() -> T, T: Send
     {
+        #[cfg(debug_assertions)]
+        let work = deadlock::check_sync(self, "dispatch_async_and_wait", work);
+
+        let work = metrics::instrument(self, Duration::ZERO, work);
         let mut result = None;
         {
             let result_ref = &mut result;
@@ -86,19 +129,29 @@ impl Queue {
         result.unwrap()
     }
 
-    pub fn dispatch_async<F>(&self, work: F) where F: 'static + Send + FnOnce() {
+    pub fn dispatch_async<F>(&self, work: F) -> WorkItem where F: 'static + Send + FnOnce() {
+        let (item, work) = WorkItem::wrap(work);
+        #[cfg(target_os = "linux")]
+        let work = qos::apply_niceness(self, work);
+        let work = metrics::instrument(self, Duration::ZERO, work);
         let (context, work) = get_context_and_async_fn(work);
         unsafe {
             dispatch_async_f(self.ptr, context, work);
         }
+        item
     }
 
-    pub fn dispatch_async_after<F>(&self, work: F, delay: Duration) where F: 'static + Send + FnOnce() {
+    pub fn dispatch_async_after<F>(&self, work: F, delay: Duration) -> WorkItem where F: 'static + Send + FnOnce() {
         let when = get_time_after_delay(delay);
+        let (item, work) = WorkItem::wrap(work);
+        #[cfg(target_os = "linux")]
+        let work = qos::apply_niceness(self, work);
+        let work = metrics::instrument(self, delay, work);
         let (context, work) = get_context_and_async_fn(work);
         unsafe {
             dispatch_after_f(when, self.ptr, context, work);
         }
+        item
     }
 
     pub fn dispatch_apply<F>(&self, iterations: usize, work: F) where F: 'static + Send + Fn(usize) {
@@ -108,9 +161,97 @@ impl Queue {
         }
     }
 
+    // dispatch_apply_f only returns once every iteration has run, so the closure may borrow
+    // from the caller. A panic in any iteration is re-raised here after all of them finished.
+    pub fn dispatch_apply_scoped<F>(&self, iterations: usize, work: F) where F: Sync + Fn(usize) {
+        let first_panic = Mutex::new(None);
+        let work = |i: usize| {
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| work(i))) {
+                first_panic.lock().unwrap().get_or_insert(payload);
+            }
+        };
+
+        let (context, work) = get_context_and_apply_fn(&work);
+        unsafe {
+            dispatch_apply_f(iterations, self.ptr, context, work);
+        }
+
+        if let Some(payload) = first_panic.into_inner().unwrap() {
+            panic::resume_unwind(payload);
+        }
+    }
+
+    pub fn apply_map<T, F>(&self, iterations: usize, work: F) -> Vec<T>
+        where F: Sync + Fn(usize) -> T, T: Send
+    {
+        let results: Vec<Mutex<Option<T>>> = (0..iterations).map(|_| Mutex::new(None)).collect();
+        self.dispatch_apply_scoped(iterations, |i| {
+            *results[i].lock().unwrap() = Some(work(i));
+        });
+
+        results
+            .into_iter()
+            .map(|result| result.into_inner().unwrap().unwrap())
+            .collect()
+    }
+
+    pub fn apply_reduce<T, A, F, R>(&self, iterations: usize, work: F, init: A, reduce: R) -> A
+        where F: Sync + Fn(usize) -> T, T: Send, R: FnMut(A, T) -> A
+    {
+        self.apply_map(iterations, work).into_iter().fold(init, reduce)
+    }
+
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+        where F: 'static + Send + Future, F::Output: 'static + Send
+    {
+        executor::spawn(self, future)
+    }
+
+    pub fn enable_metrics(&self) {
+        metrics::enable(self);
+    }
+
+    pub fn metrics(&self) -> Option<QueueMetricsSnapshot> {
+        metrics::get(self)
+    }
+
     pub fn suspend(&self) -> QueueExecutionGuard {
         QueueExecutionGuard::new(self)
     }
+
+    // Work submitted to this queue is executed on `target`; a serial queue targeting a
+    // concurrent queue still runs one item at a time, but shares the target's width.
+    pub fn set_target(&self, target: &Queue) {
+        unsafe {
+            dispatch_set_target_queue(self.ptr, target.ptr);
+        }
+        deadlock::retarget(self, target);
+    }
+
+    pub fn set_specific<T, V>(&self, key: &'static QueueKey<T>, value: V)
+        where T: 'static + Send + Sync, V: Into<Arc<T>>
+    {
+        let (context, destructor) = specific_value::get_context_and_release_fn(key, value.into());
+        unsafe {
+            dispatch_queue_set_specific(self.ptr, key.as_ptr(), context, Some(destructor));
+        }
+    }
+
+    pub fn remove_specific<T>(&self, key: &'static QueueKey<T>) {
+        unsafe {
+            dispatch_queue_set_specific(self.ptr, key.as_ptr(), std::ptr::null_mut(), None);
+        }
+    }
+
+    pub fn get_specific<T>(&self, key: &'static QueueKey<T>) -> Option<Arc<T>> where T: 'static + Send + Sync {
+        unsafe { specific_value::read(key, || dispatch_queue_get_specific(self.ptr, key.as_ptr())) }
+    }
+
+    // Looks the key up on the queue the caller is currently running on, following its
+    // target queue chain, so a value set on a shared target is visible to every source queue.
+    pub fn get_current_specific<T>(key: &'static QueueKey<T>) -> Option<Arc<T>> where T: 'static + Send + Sync {
+        unsafe { specific_value::read(key, || dispatch_get_specific(key.as_ptr())) }
+    }
 }
 
 unsafe impl Sync for Queue {}
@@ -163,6 +304,7 @@ impl Drop for QueueExecutionGuard {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::queue::attr::QueueAttr;
     use std::sync::mpsc;
     use std::time::Duration;
 
@@ -190,6 +332,20 @@ mod tests {
         assert_eq!(custom_queue.label(), "com.example.myqueue");
     }
 
+    // Test the creation of a queue with a QoS class
+    #[test]
+    fn test_create_queue_with_qos() {
+        let queue = Queue::create("com.example.myqueue", QueueAttr::Serial.with_qos(Qos::new(QosClass::Utility, -2)));
+        assert_eq!(queue.qos(), Ok(Qos::new(QosClass::Utility, -2)));
+    }
+
+    // Test the creation of a global queue from a QoS class
+    #[test]
+    fn test_global_queue_with_qos() {
+        let global_queue = Queue::global_with_qos(QosClass::UserInteractive);
+        assert_eq!(global_queue.label(), "com.apple.root.user-interactive-qos");
+    }
+
     // Test getting the label of a queue
     #[test]
     fn test_queue_label() {
@@ -266,6 +422,127 @@ mod tests {
         }
     }
 
+    // Test funneling a serial queue into a concurrent target
+    #[test]
+    fn test_set_target() {
+        let target = Queue::create("com.example.target", QueueAttr::Concurrent);
+        let queue = Queue::create("com.example.myqueue", QueueAttr::Serial);
+        queue.set_target(&target);
+
+        static TARGET_KEY: QueueKey<&'static str> = QueueKey::new();
+        target.set_specific(&TARGET_KEY, "target");
+
+        let seen = queue.dispatch_sync(|| Queue::get_current_specific(&TARGET_KEY));
+        assert_eq!(seen.as_deref(), Some(&"target"));
+    }
+
+    // Test storing and reading queue-specific values
+    #[test]
+    fn test_queue_specific() {
+        static KEY: QueueKey<u32> = QueueKey::new();
+        let queue = Queue::create("com.example.myqueue", QueueAttr::Serial);
+        assert_eq!(queue.get_specific(&KEY), None);
+
+        queue.set_specific(&KEY, 7);
+        assert_eq!(queue.get_specific(&KEY).as_deref(), Some(&7));
+        assert_eq!(Queue::get_current_specific(&KEY), None);
+        assert_eq!(queue.dispatch_sync(|| Queue::get_current_specific(&KEY)).as_deref(), Some(&7));
+
+        queue.remove_specific(&KEY);
+        assert_eq!(queue.get_specific(&KEY), None);
+    }
+
+    // Test that re-entering the current serial queue is reported instead of hanging
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_dispatch_sync_reentrancy_panics() {
+        let queue = Queue::create("com.example.myqueue", QueueAttr::Serial);
+        let inner = queue.clone();
+        let panicked = queue.dispatch_sync(move || {
+            panic::catch_unwind(AssertUnwindSafe(|| inner.dispatch_sync(|| ()))).is_err()
+        });
+        assert!(panicked);
+    }
+
+    // Test that re-entrancy is detected through a shared serial target
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_dispatch_sync_reentrancy_through_target_panics() {
+        let target = Queue::create("com.example.target", QueueAttr::Serial);
+        let first = Queue::create("com.example.first", QueueAttr::Concurrent);
+        let second = Queue::create("com.example.second", QueueAttr::Concurrent);
+        first.set_target(&target);
+        second.set_target(&target);
+
+        let panicked = first.dispatch_sync(move || {
+            panic::catch_unwind(AssertUnwindSafe(|| second.dispatch_sync(|| ()))).is_err()
+        });
+        assert!(panicked);
+    }
+
+    // Test that a queue held further up a chain of synchronous calls is detected
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_dispatch_sync_nested_reentrancy_panics() {
+        let first = Queue::create("com.example.first", QueueAttr::Serial);
+        let second = Queue::create("com.example.second", QueueAttr::Serial);
+        let inner = first.clone();
+        let panicked = first.dispatch_sync(move || {
+            second.dispatch_sync(move || {
+                panic::catch_unwind(AssertUnwindSafe(|| inner.dispatch_sync(|| ()))).is_err()
+            })
+        });
+        assert!(panicked);
+    }
+
+    // Test that a concurrent queue can still be re-entered
+    #[test]
+    fn test_dispatch_sync_reentrancy_on_concurrent_queue() {
+        let queue = Queue::create("com.example.myqueue", QueueAttr::Concurrent);
+        let inner = queue.clone();
+        let result = queue.dispatch_sync(move || inner.dispatch_sync(|| 42));
+        assert_eq!(result, 42);
+    }
+
+    // Test scoped dispatch apply borrowing local data
+    #[test]
+    fn test_dispatch_apply_scoped() {
+        let queue = Queue::create("com.example.myqueue", QueueAttr::Concurrent);
+        let values = [1, 2, 3, 4];
+        let total = Mutex::new(0);
+        queue.dispatch_apply_scoped(values.len(), |i| {
+            *total.lock().unwrap() += values[i];
+        });
+        assert_eq!(total.into_inner().unwrap(), 10);
+    }
+
+    // Test collecting per-iteration results in order
+    #[test]
+    fn test_apply_map() {
+        let queue = Queue::create("com.example.myqueue", QueueAttr::Concurrent);
+        let names = ["a", "bb", "ccc"];
+        let lengths = queue.apply_map(names.len(), |i| names[i].len());
+        assert_eq!(lengths, vec![1, 2, 3]);
+    }
+
+    // Test folding per-iteration results
+    #[test]
+    fn test_apply_reduce() {
+        let queue = Queue::create("com.example.myqueue", QueueAttr::Concurrent);
+        let sum = queue.apply_reduce(10, |i| i * 2, 0, |acc, value| acc + value);
+        assert_eq!(sum, 90);
+    }
+
+    // Test that a panicking iteration is propagated to the caller
+    #[test]
+    fn test_apply_map_propagates_panic() {
+        let queue = Queue::create("com.example.myqueue", QueueAttr::Concurrent);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            queue.apply_map(4, |i| if i == 2 { panic!("iteration failed") } else { i })
+        }));
+        assert!(result.is_err());
+    }
+
     // Test cloning a queue
     #[test]
     fn test_clone_queue() {
@@ -1,5 +1,8 @@
 use crate::ffi::{DISPATCH_QUEUE_PRIORITY_BACKGROUND, DISPATCH_QUEUE_PRIORITY_DEFAULT, DISPATCH_QUEUE_PRIORITY_HIGH, DISPATCH_QUEUE_PRIORITY_LOW};
+use crate::queue::error::InvalidRawValue;
+use crate::queue::qos::QosClass;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum QueuePriority {
     High,
     Default,
@@ -17,13 +20,40 @@ impl QueuePriority {
         }) as i64
     }
 
-    pub fn from_raw(raw: i64) -> Self {
+    pub fn from_raw(raw: i64) -> Result<Self, InvalidRawValue<i64>> {
         match raw {
-            DISPATCH_QUEUE_PRIORITY_HIGH => QueuePriority::High,
-            DISPATCH_QUEUE_PRIORITY_DEFAULT => QueuePriority::Default,
-            DISPATCH_QUEUE_PRIORITY_LOW => QueuePriority::Low,
-            DISPATCH_QUEUE_PRIORITY_BACKGROUND => QueuePriority::Background,
-            _ => panic!("Invalid raw value for QueuePriority"),
+            DISPATCH_QUEUE_PRIORITY_HIGH => Ok(QueuePriority::High),
+            DISPATCH_QUEUE_PRIORITY_DEFAULT => Ok(QueuePriority::Default),
+            DISPATCH_QUEUE_PRIORITY_LOW => Ok(QueuePriority::Low),
+            DISPATCH_QUEUE_PRIORITY_BACKGROUND => Ok(QueuePriority::Background),
+            _ => Err(InvalidRawValue::new("QueuePriority", raw)),
         }
     }
-}
\ No newline at end of file
+
+    // The legacy priorities are aliases for these QoS classes in libdispatch.
+    pub fn to_qos_class(&self) -> QosClass {
+        match self {
+            QueuePriority::High => QosClass::UserInitiated,
+            QueuePriority::Default => QosClass::Default,
+            QueuePriority::Low => QosClass::Utility,
+            QueuePriority::Background => QosClass::Background,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_priority_round_trip() {
+        for priority in [QueuePriority::High, QueuePriority::Default, QueuePriority::Low, QueuePriority::Background] {
+            assert_eq!(QueuePriority::from_raw(priority.to_raw()), Ok(priority));
+        }
+    }
+
+    #[test]
+    fn test_priority_from_unknown_raw() {
+        assert_eq!(QueuePriority::from_raw(1), Err(InvalidRawValue::new("QueuePriority", 1)));
+    }
+}
@@ -0,0 +1,189 @@
+use crate::ffi::{dispatch_qos_class_t, QOS_CLASS_BACKGROUND, QOS_CLASS_DEFAULT, QOS_CLASS_UNSPECIFIED, QOS_CLASS_USER_INITIATED, QOS_CLASS_USER_INTERACTIVE, QOS_CLASS_UTILITY, QOS_MIN_RELATIVE_PRIORITY};
+use crate::queue::error::{InvalidRawValue, QueueError};
+#[cfg(target_os = "linux")]
+use crate::queue::Queue;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum QosClass {
+    Unspecified,
+    Background,
+    Utility,
+    Default,
+    UserInitiated,
+    UserInteractive,
+}
+
+impl QosClass {
+    pub fn to_raw(&self) -> dispatch_qos_class_t {
+        match self {
+            QosClass::UserInteractive => QOS_CLASS_USER_INTERACTIVE,
+            QosClass::UserInitiated => QOS_CLASS_USER_INITIATED,
+            QosClass::Default => QOS_CLASS_DEFAULT,
+            QosClass::Utility => QOS_CLASS_UTILITY,
+            QosClass::Background => QOS_CLASS_BACKGROUND,
+            QosClass::Unspecified => QOS_CLASS_UNSPECIFIED,
+        }
+    }
+
+    pub fn from_raw(raw: dispatch_qos_class_t) -> Result<Self, InvalidRawValue<dispatch_qos_class_t>> {
+        match raw {
+            QOS_CLASS_USER_INTERACTIVE => Ok(QosClass::UserInteractive),
+            QOS_CLASS_USER_INITIATED => Ok(QosClass::UserInitiated),
+            QOS_CLASS_DEFAULT => Ok(QosClass::Default),
+            QOS_CLASS_UTILITY => Ok(QosClass::Utility),
+            QOS_CLASS_BACKGROUND => Ok(QosClass::Background),
+            QOS_CLASS_UNSPECIFIED => Ok(QosClass::Unspecified),
+            _ => Err(InvalidRawValue::new("QosClass", raw)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Qos {
+    pub class: QosClass,
+    pub relative_priority: i32,
+}
+
+impl Qos {
+    pub fn new(class: QosClass, relative_priority: i32) -> Self {
+        match Self::try_new(class, relative_priority) {
+            Ok(qos) => qos,
+            Err(error) => panic!("{}", error),
+        }
+    }
+
+    pub fn try_new(class: QosClass, relative_priority: i32) -> Result<Self, QueueError> {
+        if !(QOS_MIN_RELATIVE_PRIORITY..=0).contains(&relative_priority) {
+            return Err(QueueError::InvalidRelativePriority(relative_priority));
+        }
+
+        Ok(Qos { class, relative_priority })
+    }
+
+    // The niceness a thread running work of this QoS gets where the kernel has no QoS
+    // classes (Linux). Interactive work is only slightly favoured, as lowering the
+    // niceness needs privileges, while background work is pushed far enough down that
+    // it never starves interactive consumers.
+    pub fn to_nice(&self) -> i32 {
+        let base = match self.class {
+            QosClass::UserInteractive => -5,
+            QosClass::UserInitiated => -2,
+            QosClass::Default | QosClass::Unspecified => 0,
+            QosClass::Utility => 5,
+            QosClass::Background => 15,
+        };
+
+        (base - self.relative_priority / 4).clamp(-20, 19)
+    }
+
+    // Applies `to_nice` to the calling thread; Linux niceness is per thread.
+    #[cfg(target_os = "linux")]
+    pub fn apply_to_current_thread(&self) -> std::io::Result<()> {
+        set_current_thread_nice(self.to_nice())
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub fn current_thread_nice() -> std::io::Result<i32> {
+    // -1 is a valid niceness, only errno tells it from a failure.
+    unsafe {
+        *libc::__errno_location() = 0;
+        let nice = libc::getpriority(libc::PRIO_PROCESS, libc::gettid() as libc::id_t);
+        if nice == -1 && *libc::__errno_location() != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(nice)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_current_thread_nice(nice: i32) -> std::io::Result<()> {
+    let result = unsafe {
+        libc::setpriority(libc::PRIO_PROCESS, libc::gettid() as libc::id_t, nice)
+    };
+    if result == 0 { Ok(()) } else { Err(std::io::Error::last_os_error()) }
+}
+
+#[cfg(target_os = "linux")]
+struct RestoreNice(i32);
+
+#[cfg(target_os = "linux")]
+impl Drop for RestoreNice {
+    fn drop(&mut self) {
+        let _ = set_current_thread_nice(self.0);
+    }
+}
+
+// libdispatch has no QoS classes on Linux, so asynchronous work on a queue with one runs
+// at its niceness instead. Worker threads are shared between queues, so the previous
+// niceness is restored afterwards; unprivileged processes can only do that when it was
+// not lower. Synchronous work runs for a waiting caller and keeps the caller's niceness,
+// as a QoS override would on macOS.
+#[cfg(target_os = "linux")]
+pub(crate) fn apply_niceness<F>(queue: &Queue, work: F) -> impl FnOnce() where F: FnOnce() {
+    let qos = queue.qos().ok().filter(|qos| qos.class != QosClass::Unspecified);
+
+    move || {
+        let _restore = qos.and_then(|qos| {
+            let previous = current_thread_nice().ok()?;
+            (previous != qos.to_nice() && qos.apply_to_current_thread().is_ok()).then_some(RestoreNice(previous))
+        });
+        work()
+    }
+}
+
+impl From<QosClass> for Qos {
+    fn from(class: QosClass) -> Self {
+        Qos { class, relative_priority: 0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_qos_class_round_trip() {
+        for class in [QosClass::Unspecified, QosClass::Background, QosClass::Utility, QosClass::Default, QosClass::UserInitiated, QosClass::UserInteractive] {
+            assert_eq!(QosClass::from_raw(class.to_raw()), Ok(class));
+        }
+    }
+
+    #[test]
+    fn test_qos_class_from_unknown_raw() {
+        assert!(QosClass::from_raw(0x42).is_err());
+    }
+
+    #[test]
+    fn test_qos_try_new() {
+        assert_eq!(Qos::try_new(QosClass::Utility, -4), Ok(Qos { class: QosClass::Utility, relative_priority: -4 }));
+        assert_eq!(Qos::try_new(QosClass::Utility, 1), Err(QueueError::InvalidRelativePriority(1)));
+        assert_eq!(Qos::try_new(QosClass::Utility, QOS_MIN_RELATIVE_PRIORITY - 1), Err(QueueError::InvalidRelativePriority(QOS_MIN_RELATIVE_PRIORITY - 1)));
+    }
+
+    #[test]
+    fn test_qos_to_nice() {
+        assert_eq!(Qos::from(QosClass::UserInteractive).to_nice(), -5);
+        assert_eq!(Qos::from(QosClass::Default).to_nice(), 0);
+        assert_eq!(Qos::new(QosClass::Background, QOS_MIN_RELATIVE_PRIORITY).to_nice(), 18);
+        assert!(Qos::from(QosClass::Background).to_nice() > Qos::from(QosClass::Utility).to_nice());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_background_queue_runs_niced() {
+        use std::sync::mpsc;
+        use crate::queue::attr::QueueAttr;
+
+        let queue = Queue::create("com.example.background", QueueAttr::Serial.with_qos(QosClass::Background));
+        let (sender, receiver) = mpsc::channel();
+        queue.dispatch_async(move || sender.send(current_thread_nice().unwrap()).unwrap());
+        assert_eq!(receiver.recv().unwrap(), Qos::from(QosClass::Background).to_nice());
+    }
+
+    #[test]
+    #[should_panic(expected = "Relative priority")]
+    fn test_qos_rejects_positive_relative_priority() {
+        Qos::new(QosClass::Utility, 1);
+    }
+}
@@ -0,0 +1,57 @@
+use std::ffi::c_void;
+use std::marker::PhantomData;
+use std::sync::{Arc, PoisonError, RwLock};
+use crate::ffi::dispatch_function_t;
+
+// libdispatch releases a replaced value whenever it likes, possibly while another thread
+// is still reading it. Readers of a key take their own reference under the key's read
+// lock and the release waits for the write lock, so a value is never freed between being
+// looked up and being retained, while lookups never wait for each other.
+pub struct QueueKey<T> {
+    retention: RwLock<()>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> QueueKey<T> {
+    pub const fn new() -> Self {
+        QueueKey { retention: RwLock::new(()), _marker: PhantomData }
+    }
+
+    pub(crate) fn as_ptr(&'static self) -> *const c_void {
+        self as *const Self as *const c_void
+    }
+}
+
+impl<T> Default for QueueKey<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// What the context of a queue-specific value points to.
+struct Entry<T: 'static> {
+    key: &'static QueueKey<T>,
+    value: Arc<T>,
+}
+
+extern "C" fn release_fn<T: 'static>(context: *mut c_void) {
+    let entry = unsafe { Box::from_raw(context as *mut Entry<T>) };
+    drop(entry.key.retention.write().unwrap_or_else(PoisonError::into_inner));
+    drop(entry);
+}
+
+pub(crate) fn get_context_and_release_fn<T: 'static>(key: &'static QueueKey<T>, value: Arc<T>) -> (*mut c_void, dispatch_function_t) {
+    (Box::into_raw(Box::new(Entry { key, value })) as *mut c_void, release_fn::<T>)
+}
+
+// `lookup` must return a context set for `key` through `get_context_and_release_fn`, or
+// null.
+pub(crate) unsafe fn read<T: 'static, L>(key: &'static QueueKey<T>, lookup: L) -> Option<Arc<T>> where L: FnOnce() -> *mut c_void {
+    let _guard = key.retention.read().unwrap_or_else(PoisonError::into_inner);
+    let entry = lookup() as *const Entry<T>;
+    if entry.is_null() {
+        return None;
+    }
+
+    Some((*entry).value.clone())
+}
@@ -0,0 +1,143 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use crate::queue::Queue;
+
+struct WorkItemStatus {
+    completed: bool,
+    notifications: Vec<(Queue, Box<dyn FnOnce() + Send>)>,
+}
+
+struct WorkItemState {
+    cancelled: AtomicBool,
+    status: Mutex<WorkItemStatus>,
+    condvar: Condvar,
+}
+
+#[derive(Clone)]
+pub struct WorkItem {
+    state: Arc<WorkItemState>,
+}
+
+impl WorkItem {
+    pub(crate) fn wrap<F>(work: F) -> (WorkItem, impl FnOnce() + Send + 'static)
+        where F: 'static + Send + FnOnce()
+    {
+        let item = WorkItem {
+            state: Arc::new(WorkItemState {
+                cancelled: AtomicBool::new(false),
+                status: Mutex::new(WorkItemStatus { completed: false, notifications: Vec::new() }),
+                condvar: Condvar::new(),
+            }),
+        };
+
+        let handle = item.clone();
+        let work = move || {
+            if !handle.is_cancelled() {
+                work();
+            }
+            handle.complete();
+        };
+
+        (item, work)
+    }
+
+    // Like dispatch_block_cancel: a work item that has not started yet is skipped when it
+    // is dequeued, one that is already running is left to finish.
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub fn wait(&self) {
+        let mut status = self.state.status.lock().unwrap();
+        while !status.completed {
+            status = self.state.condvar.wait(status).unwrap();
+        }
+    }
+
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut status = self.state.status.lock().unwrap();
+        while !status.completed {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            status = self.state.condvar.wait_timeout(status, deadline - now).unwrap().0;
+        }
+        true
+    }
+
+    pub fn notify<F>(&self, queue: &Queue, work: F)
+        where F: 'static + Send + FnOnce()
+    {
+        let mut status = self.state.status.lock().unwrap();
+        if status.completed {
+            drop(status);
+            queue.dispatch_async(work);
+            return;
+        }
+
+        status.notifications.push((queue.clone(), Box::new(work)));
+    }
+
+    fn complete(&self) {
+        let notifications = {
+            let mut status = self.state.status.lock().unwrap();
+            status.completed = true;
+            self.state.condvar.notify_all();
+            std::mem::take(&mut status.notifications)
+        };
+
+        for (queue, work) in notifications {
+            queue.dispatch_async(work);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use crate::queue::attr::QueueAttr;
+
+    #[test]
+    fn test_wait_for_work_item() {
+        let queue = Queue::create("com.example.myqueue", QueueAttr::Serial);
+        let (tx, rx) = mpsc::channel();
+        let item = queue.dispatch_async(move || {
+            tx.send(42).unwrap();
+        });
+        item.wait();
+        assert_eq!(rx.try_recv().unwrap(), 42);
+        assert!(!item.is_cancelled());
+    }
+
+    #[test]
+    fn test_cancel_delayed_work_item() {
+        let queue = Queue::create("com.example.myqueue", QueueAttr::Serial);
+        let (tx, rx) = mpsc::channel();
+        let item = queue.dispatch_async_after(move || {
+            tx.send(42).unwrap();
+        }, Duration::from_millis(200));
+        item.cancel();
+        assert!(item.wait_timeout(Duration::from_secs(5)));
+        assert!(item.is_cancelled());
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_notify_after_work_item() {
+        let queue = Queue::create("com.example.myqueue", QueueAttr::Serial);
+        let (tx, rx) = mpsc::channel();
+        let item = queue.dispatch_async(|| {});
+        item.notify(&queue, move || {
+            tx.send(12).unwrap();
+        });
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), 12);
+    }
+}